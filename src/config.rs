@@ -0,0 +1,99 @@
+use crate::EnvEditError;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Default)]
+pub struct Config {
+    pub editor: Option<String>,
+    // `list.NAME = SEP` entries, in file order
+    pub lists: Vec<(String, String)>,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("envedit/config")),
+            _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/envedit/config")),
+        }
+    }
+
+    // the config file is a list of `key = value` lines; blank lines and
+    // lines starting with '#' are ignored
+    fn parse(text: &str) -> Result<Config, EnvEditError> {
+        let mut config = Config::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    return Err(EnvEditError::Config(format!(
+                        "Error reading config: line {} is malformed; missing '=' separator",
+                        index + 1
+                    )))
+                }
+            };
+
+            match key {
+                "editor" => config.editor = Some(String::from(value)),
+                _ if key.starts_with("list.") => {
+                    let name = &key["list.".len()..];
+                    config.lists.push((String::from(name), String::from(value)));
+                }
+                _ => {
+                    return Err(EnvEditError::Config(format!(
+                        "Error reading config: line {} has unknown key '{}'",
+                        index + 1,
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load() -> Result<Config, EnvEditError> {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(EnvEditError::Config(format!(
+                "Error reading config {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse("# comment\n\n  editor = code --wait  \n").unwrap();
+        assert_eq!(config.editor.as_deref(), Some("code --wait"));
+
+        let config = Config::parse("list.MY_FLAGS = ,\nlist.PATH =\n").unwrap();
+        assert_eq!(
+            config.lists,
+            [
+                (String::from("MY_FLAGS"), String::from(",")),
+                (String::from("PATH"), String::new())
+            ]
+        );
+
+        assert!(Config::parse("editor").is_err());
+        assert!(Config::parse("colour = always").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::EnvEditError;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// tried in order when nothing else names an editor
+const FALLBACK_EDITORS: [&str; 4] = ["nvim", "vim", "vi", "nano"]; // cspell:disable-line
+
+pub struct Editor {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Editor {
+    // editor strings may carry their own arguments, e.g. `code --wait`, so
+    // they are split into words the same way a shell would
+    pub fn parse(command: &str) -> Result<Editor, EnvEditError> {
+        let mut words = shell_words::split(command).map_err(|e| {
+            EnvEditError::EditorNotFound(format!("Invalid editor '{}': {}", command, e))
+        })?;
+        if words.is_empty() {
+            return Err(EnvEditError::EditorNotFound(String::from(
+                "Invalid editor: command is empty",
+            )));
+        }
+
+        let program = words.remove(0);
+        Ok(Editor {
+            program,
+            args: words,
+        })
+    }
+
+    // the editor is chosen from the --editor flag, $VISUAL, $EDITOR, the
+    // config file and finally the first fallback editor found in $PATH
+    pub fn resolve(flag: Option<&str>, config: &Config) -> Result<Editor, EnvEditError> {
+        if let Some(command) = flag {
+            return Editor::parse(command);
+        }
+
+        for var in ["VISUAL", "EDITOR"] {
+            if let Some(command) = env::var(var).ok().filter(|c| !c.trim().is_empty()) {
+                return Editor::parse(&command);
+            }
+        }
+
+        if let Some(command) = &config.editor {
+            return Editor::parse(command);
+        }
+
+        for program in FALLBACK_EDITORS {
+            if in_path(program) {
+                return Editor::parse(program);
+            }
+        }
+
+        Err(EnvEditError::EditorNotFound(String::from(
+            "No editor found; set $VISUAL or $EDITOR, or pass --editor",
+        )))
+    }
+
+    // arguments that tell the editor to highlight the file as shell
+    // assignments, for the editors we know how to ask
+    fn filetype_args(&self) -> &'static [&'static str] {
+        let name = Path::new(&self.program)
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+        match name {
+            "vi" | "vim" | "nvim" | "gvim" | "mvim" => &["-c", "set filetype=sh"], // cspell:disable-line
+            "nano" => &["--syntax=sh"],
+            "kak" => &["-e", "set buffer filetype sh"],
+            "micro" => &["-filetype", "shell"],
+            _ => &[],
+        }
+    }
+
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    pub fn command(&self, path: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .args(self.filetype_args())
+            .arg(path);
+        command
+    }
+}
+
+pub enum Recovery {
+    Edit,
+    Discard,
+    Abort,
+}
+
+// without a terminal to answer on, a parse error is simply reported
+pub fn can_ask() -> bool {
+    io::stdin().is_terminal()
+}
+
+// asks what to do about an edit file that failed to parse, the way visudo
+// does. stdout may be captured by the calling shell, so this talks on stderr
+pub fn ask_recovery() -> Recovery {
+    loop {
+        eprint!("What now? (e)dit again, (d)iscard changes, (a)bort: ");
+        let mut answer = String::new();
+        match io::stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => return Recovery::Abort,
+            Ok(_) => {}
+        }
+        match answer.trim() {
+            "e" | "E" => return Recovery::Edit,
+            "d" | "D" => return Recovery::Discard,
+            "a" | "A" => return Recovery::Abort,
+            _ => {}
+        }
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    let paths = match env::var_os("PATH") {
+        Some(paths) => paths,
+        None => return false,
+    };
+    env::split_paths(&paths).any(|dir| match dir.join(program).metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+    use std::path::Path;
+
+    fn args(editor: &Editor) -> Vec<String> {
+        let command = editor.command(Path::new("/tmp/env"));
+        let mut args = vec![command.get_program().to_string_lossy().into_owned()];
+        args.extend(command.get_args().map(|a| a.to_string_lossy().into_owned()));
+        args
+    }
+
+    #[test]
+    fn editor_arguments() {
+        let editor = Editor::parse("code --wait").unwrap();
+        assert_eq!(args(&editor), ["code", "--wait", "/tmp/env"]);
+
+        let editor = Editor::parse("'/opt/my editor/bin/emacsclient' -t").unwrap();
+        assert_eq!(
+            args(&editor),
+            ["/opt/my editor/bin/emacsclient", "-t", "/tmp/env"]
+        );
+
+        let editor = Editor::parse("/usr/bin/nvim").unwrap(); // cspell:disable-line
+        assert_eq!(
+            args(&editor),
+            ["/usr/bin/nvim", "-c", "set filetype=sh", "/tmp/env"] // cspell:disable-line
+        );
+
+        assert!(Editor::parse("  ").is_err());
+        assert!(Editor::parse("vim 'unterminated").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{DiffEntry, DiffState, EnvEditError};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use tempfile::NamedTempFile;
+
+// the child starts from our own environment, so only the differences need
+// to be applied to it
+fn apply(diff: &[DiffEntry], command: &mut Command) {
+    for entry in diff {
+        match entry.state {
+            DiffState::Added | DiffState::Modified => {
+                command.env(&entry.name, entry.new_value.as_deref().unwrap());
+            }
+            DiffState::Deleted => {
+                command.env_remove(&entry.name);
+            }
+            DiffState::Renamed => {
+                command.env_remove(entry.old_name.as_deref().unwrap());
+                command.env(&entry.name, entry.new_value.as_deref().unwrap());
+            }
+            DiffState::Unchanged => {}
+        }
+    }
+}
+
+// runs `program` under the edited environment and returns the exit code to
+// pass on; like a shell, a command killed by a signal reports 128 + signal
+pub fn run(diff: &[DiffEntry], program: &OsStr, args: &[&OsStr]) -> i32 {
+    let mut command = Command::new(program);
+    command.args(args);
+    apply(diff, &mut command);
+
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("envedit: {}: {}", program.to_string_lossy(), e);
+            return 127;
+        }
+    };
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+// records the environment, sources the script with the remaining arguments
+// and records it again. the script's output goes to stderr so it can't mix
+// with ours
+const CAPTURE_SCRIPT: &str = r#"__envedit_before=$1 __envedit_after=$2 __envedit_script=$3
+shift 3
+env -0 >"$__envedit_before" || exit
+. "$__envedit_script" >&2
+__envedit_status=$?
+env -0 >"$__envedit_after"
+exit "$__envedit_status"
+"#;
+
+// the raw `env -0` output from before and after sourcing a script
+pub struct Capture {
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+    pub status: ExitStatus,
+}
+
+// sources `script` in a child `shell` and captures the environment around
+// it. a script that exits the shell leaves nothing to compare
+pub fn capture(shell: &OsStr, script: &OsStr, args: &[&OsStr]) -> Result<Capture, EnvEditError> {
+    fs::metadata(script).map_err(|e| {
+        let context = format!("Failed to open {}", script.to_string_lossy());
+        EnvEditError::io(&context, e)
+    })?;
+
+    let temp_error = |e| EnvEditError::io("Failed to create temp file", e);
+    let before = NamedTempFile::new().map_err(temp_error)?;
+    let after = NamedTempFile::new().map_err(temp_error)?;
+
+    // like `.` in most shells, a bare name would be looked up in $PATH
+    let script = if script.as_bytes().contains(&b'/') {
+        script.to_os_string()
+    } else {
+        let mut relative = OsString::from("./");
+        relative.push(script);
+        relative
+    };
+
+    let status = Command::new(shell)
+        .arg("-c")
+        .arg(CAPTURE_SCRIPT)
+        .arg("envedit")
+        .arg(before.path())
+        .arg(after.path())
+        .arg(&script)
+        .args(args)
+        .status()
+        .map_err(|e| {
+            let context = format!("Failed to run {}", shell.to_string_lossy());
+            EnvEditError::io(&context, e)
+        })?;
+
+    let read_error = |e| EnvEditError::io("Failed to read captured environment", e);
+    let before = fs::read(before.path()).map_err(read_error)?;
+    let after = fs::read(after.path()).map_err(read_error)?;
+    if before.is_empty() || after.is_empty() {
+        return Err(EnvEditError::ScriptFailed(status));
+    }
+    Ok(Capture {
+        before,
+        after,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{capture, run};
+    use crate::{DiffEntry, DiffState};
+    use std::ffi::{OsStr, OsString};
+    use std::io::Write;
+
+    #[test]
+    fn run_with_diff() {
+        let diff = vec![
+            DiffEntry {
+                name: OsString::from("ENVEDIT_TEST_ADDED"),
+                old_name: None,
+                state: DiffState::Added,
+                old_value: None,
+                new_value: Some(OsString::from("a b")),
+                elements: None,
+            },
+            DiffEntry {
+                name: OsString::from("HOME"),
+                old_name: None,
+                state: DiffState::Deleted,
+                old_value: Some(OsString::from("/home")),
+                new_value: None,
+                elements: None,
+            },
+        ];
+        let script = OsStr::new(r#"test "$ENVEDIT_TEST_ADDED" = "a b" && test -z "${HOME+set}""#);
+        assert_eq!(run(&diff, OsStr::new("sh"), &[OsStr::new("-c"), script]), 0);
+
+        let exit = OsStr::new("exit 3");
+        assert_eq!(run(&[], OsStr::new("sh"), &[OsStr::new("-c"), exit]), 3);
+        assert_eq!(run(&[], OsStr::new("/nonexistent/envedit-test"), &[]), 127);
+    }
+
+    #[test]
+    fn capture_sourced_script() {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "export ENVEDIT_TEST_SOURCED=\"$1\"\nreturn 2").unwrap();
+
+        let captured = capture(
+            OsStr::new("sh"),
+            script.path().as_os_str(),
+            &[OsStr::new("a b")],
+        )
+        .unwrap();
+        let contains = |env: &[u8], entry: &[u8]| env.split(|b| *b == 0).any(|e| e == entry);
+        assert!(!contains(&captured.before, b"ENVEDIT_TEST_SOURCED=a b"));
+        assert!(contains(&captured.after, b"ENVEDIT_TEST_SOURCED=a b"));
+        assert_eq!(captured.status.code(), Some(2));
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "exit 1").unwrap();
+        let result = capture(OsStr::new("sh"), script.path().as_os_str(), &[]);
+        assert!(result.is_err());
+    }
+}
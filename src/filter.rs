@@ -0,0 +1,143 @@
+use crate::EnvEditError;
+use regex::bytes::Regex;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+
+// matches `name` against a shell-style glob: '*' matches any run of bytes,
+// '?' any single byte and '[...]' a set such as '[A-Z_]' or '[!0-9]'
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(b'*') => (0..=name.len()).any(|i| glob_match(&pattern[1..], &name[i..])),
+        Some(b'?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some(b'[') => match (class_match(&pattern[1..], name.first()), name.is_empty()) {
+            (Some((true, rest)), false) => glob_match(rest, &name[1..]),
+            (Some(_), _) => false,
+            // an unterminated '[' is just a literal
+            (None, _) => name.first() == Some(&b'[') && glob_match(&pattern[1..], &name[1..]),
+        },
+        Some(c) => name.first() == Some(c) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+// matches the set starting just after '[', returning whether `byte` is in it
+// and the rest of the pattern after the closing ']'
+fn class_match<'a>(pattern: &'a [u8], byte: Option<&u8>) -> Option<(bool, &'a [u8])> {
+    let (negated, mut i) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+
+    // a ']' straight after the '[' is part of the set
+    while i < pattern.len() && (pattern[i] != b']' || first) {
+        first = false;
+        let low = pattern[i];
+        let high =
+            if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() && pattern[i + 2] != b']'
+            {
+                i += 2;
+                pattern[i]
+            } else {
+                low
+            };
+        if let Some(&byte) = byte {
+            matched |= low <= byte && byte <= high;
+        }
+        i += 1;
+    }
+
+    if i >= pattern.len() {
+        return None;
+    }
+    Some((matched != negated, &pattern[i + 1..]))
+}
+
+// selects which variables are edited. a variable is selected if it matches
+// any of the names, globs or regexes given
+#[derive(Default)]
+pub struct Filter {
+    names: Vec<OsString>,
+    globs: Vec<OsString>,
+    regexes: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn add_name(&mut self, name: &OsStr) {
+        self.names.push(name.to_os_string());
+    }
+
+    pub fn add_glob(&mut self, glob: &OsStr) {
+        self.globs.push(glob.to_os_string());
+    }
+
+    pub fn add_regex(&mut self, regex: &str) -> Result<(), EnvEditError> {
+        let regex = Regex::new(regex)
+            .map_err(|e| EnvEditError::Usage(format!("Invalid regex '{}': {}", regex, e)))?;
+        self.regexes.push(regex);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    pub fn names(&self) -> &[OsString] {
+        &self.names
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        self.names.iter().any(|n| n == name)
+            || self
+                .globs
+                .iter()
+                .any(|glob| glob_match(glob.as_bytes(), name.as_bytes()))
+            || self
+                .regexes
+                .iter()
+                .any(|regex| regex.is_match(name.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{glob_match, Filter};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match(b"AWS_*", b"AWS_PROFILE"));
+        assert!(glob_match(b"AWS_*", b"AWS_"));
+        assert!(!glob_match(b"AWS_*", b"MY_AWS_PROFILE"));
+        assert!(glob_match(b"*PATH", b"LD_LIBRARY_PATH"));
+        assert!(glob_match(b"*_*_*", b"A_B_C"));
+        assert!(glob_match(b"?", b"X"));
+        assert!(!glob_match(b"?", b""));
+        assert!(glob_match(b"GO[A-Z]*", b"GOPATH"));
+        assert!(!glob_match(b"GO[!A-Z]*", b"GOPATH"));
+        assert!(glob_match(b"X[]]", b"X]"));
+        assert!(glob_match(b"X[-]", b"X-"));
+        assert!(glob_match(b"X[", b"X["));
+        assert!(!glob_match(b"HOME", b"HOMEDIR"));
+    }
+
+    #[test]
+    fn filter_matches_any() {
+        let mut filter = Filter::default();
+        assert!(filter.is_empty());
+
+        filter.add_name(OsStr::new("PATH"));
+        filter.add_glob(OsStr::new("AWS_*"));
+        filter.add_regex("^KUBE").unwrap();
+        assert!(!filter.is_empty());
+
+        assert!(filter.matches(OsStr::new("PATH")));
+        assert!(filter.matches(OsStr::new("AWS_REGION")));
+        assert!(filter.matches(OsStr::new("KUBECONFIG")));
+        assert!(!filter.matches(OsStr::new("MANPATH")));
+        assert!(!filter.matches(OsStr::new("MY_KUBE")));
+
+        assert!(filter.add_regex("(").is_err());
+    }
+}
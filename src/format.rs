@@ -0,0 +1,603 @@
+// the edit file holds one `NAME=value` assignment per line. anything that
+// can't be written verbatim on a single line is written in bash's ANSI-C
+// quoting instead, `NAME=$'...'`, so every environment survives a round trip
+// through the editor byte for byte.
+//
+// list variables such as PATH are written as a block with one element per
+// line, between `NAME=(` and a closing `)`.
+//
+// blank lines and lines starting with '#' are ignored. so that lines pasted
+// from shell scripts read the way the shell would read them, indentation
+// and a leading `export` are dropped and values or elements starting with a
+// quote are read as a shell word made of '...', "..." and $'...' strings.
+
+const QUOTE_START: &str = "$'";
+const COMMENT: &str = "#";
+const EXPORT: &str = "export";
+// marks the note left above a line that failed to parse, so it can be
+// replaced by the next one
+const ERROR_NOTE: &str = "# envedit: ";
+
+// written at the top of the edit file, in the spirit of git's commit
+// message template
+pub const HEADER: &str = "\
+# Edit the environment below, one NAME=value per line. Deleting a line
+# unsets the variable and adding one sets it.
+#
+# Values are taken verbatim after the first '='. Values that can't be, such
+# as ones with line breaks or surrounding spaces, are written as $'...'
+# using bash's escapes: \\n, \\t, \\\\, \\' and \\xHH for any other byte.
+# A value starting with a quote is read the way a shell would read it, and
+# indentation and a leading `export` are ignored.
+#
+# List variables such as PATH are written as NAME=( with one element per
+# line, up to a closing ). An element containing '=' has to be quoted.
+#
+# Blank lines and lines starting with '#' are ignored. To cancel, quit
+# without saving or make the editor exit with an error (:cq in vim).
+";
+const LIST_START: &str = "(";
+const LIST_END: &str = ")";
+const LIST_INDENT: &str = "    ";
+
+fn needs_quoting(s: &str, is_name: bool) -> bool {
+    if s.starts_with(QUOTE_START) || s.chars().any(char::is_control) {
+        return true;
+    }
+    if s == LIST_START && !is_name {
+        return true;
+    }
+    if is_name {
+        // a name ends at the first '=', so only surrounding clutter and
+        // anything that would read as a comment matters
+        s.starts_with(COMMENT) || s.chars().any(char::is_whitespace)
+    } else {
+        starts_quoted(s) || s.starts_with(char::is_whitespace) || s.ends_with(char::is_whitespace)
+    }
+}
+
+// values and elements starting like this are read as shell words
+fn starts_quoted(s: &str) -> bool {
+    s.starts_with(QUOTE_START) || s.starts_with('\'') || s.starts_with('"')
+}
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+fn push_escaped_byte(quoted: &mut String, byte: u8) {
+    quoted.push_str(&format!("\\x{:02x}", byte));
+}
+
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from(QUOTE_START);
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '\'' => quoted.push_str("\\'"),
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        push_escaped_byte(&mut quoted, byte);
+                    }
+                }
+                c => quoted.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            push_escaped_byte(&mut quoted, *byte);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+pub fn encode(bytes: &[u8], is_name: bool) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) if !needs_quoting(s, is_name) => String::from(s),
+        _ => quote(bytes),
+    }
+}
+
+pub fn encode_line(name: &[u8], value: &[u8]) -> String {
+    format!("{}={}", encode(name, true), encode(value, false))
+}
+
+// elements are trimmed when read back, so any surrounding whitespace has to
+// be quoted, as do empty elements and ones that would end the list, read as
+// a comment or read as an assignment
+pub fn encode_element(element: &[u8]) -> String {
+    match std::str::from_utf8(element) {
+        Ok(s)
+            if !s.is_empty()
+                && s != LIST_END
+                && !s.starts_with(COMMENT)
+                && !s.contains('=')
+                && !needs_quoting(s, false) =>
+        {
+            String::from(s)
+        }
+        _ => quote(element),
+    }
+}
+
+pub fn encode_list(name: &[u8], elements: &[&[u8]]) -> String {
+    let mut block = format!("{}={}\n", encode(name, true), LIST_START);
+    for element in elements {
+        block.push_str(LIST_INDENT);
+        block.push_str(&encode_element(element));
+        block.push('\n');
+    }
+    block.push_str(LIST_END);
+    block
+}
+
+fn hex_digit(c: char) -> Option<u8> {
+    c.to_digit(16).map(|d| d as u8)
+}
+
+// a line of the edit file that couldn't be read, with the 1-based column
+// the problem starts at
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub column: usize,
+    pub msg: String,
+}
+
+impl SyntaxError {
+    fn new(line: &str, index: usize, msg: &str) -> SyntaxError {
+        SyntaxError {
+            column: line[..index].chars().count() + 1,
+            msg: String::from(msg),
+        }
+    }
+}
+
+// decodes the `$'...'` string starting at byte `start` of `line`, returning
+// the decoded bytes and the index just past the closing quote
+fn unquote(line: &str, start: usize) -> Result<(Vec<u8>, usize), SyntaxError> {
+    let mut bytes = Vec::new();
+    let offset = start + QUOTE_START.len();
+    let mut chars = line[offset..].char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' => return Ok((bytes, offset + i + 1)),
+            '\\' => {
+                let (_, escape) = match chars.next() {
+                    Some(next) => next,
+                    None => break,
+                };
+                match escape {
+                    '\\' | '\'' | '"' | '?' => bytes.push(escape as u8),
+                    'a' => bytes.push(0x07),
+                    'b' => bytes.push(0x08),
+                    'e' | 'E' => bytes.push(0x1b),
+                    'f' => bytes.push(0x0c),
+                    'n' => bytes.push(b'\n'),
+                    'r' => bytes.push(b'\r'),
+                    't' => bytes.push(b'\t'),
+                    'v' => bytes.push(0x0b),
+                    'x' => {
+                        let mut byte = None;
+                        for _ in 0..2 {
+                            match chars.peek().and_then(|(_, c)| hex_digit(*c)) {
+                                Some(d) => {
+                                    byte = Some(byte.unwrap_or(0) * 16 + d);
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        match byte {
+                            Some(byte) => bytes.push(byte),
+                            None => {
+                                let msg = "'\\x' escape without hex digits";
+                                return Err(SyntaxError::new(line, offset + i, msg));
+                            }
+                        }
+                    }
+                    '0'..='7' => {
+                        let mut byte = escape as u32 - '0' as u32;
+                        for _ in 0..2 {
+                            match chars.peek().and_then(|(_, c)| c.to_digit(8)) {
+                                Some(d) => {
+                                    byte = byte * 8 + d;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        bytes.push(byte as u8);
+                    }
+                    // like bash, unknown escapes are kept as they are
+                    c => {
+                        bytes.push(b'\\');
+                        push_char(&mut bytes, c);
+                    }
+                }
+            }
+            c => push_char(&mut bytes, c),
+        }
+    }
+
+    Err(SyntaxError::new(line, start, "unterminated $'...' string"))
+}
+
+// decodes a `"..."` string starting at byte `start` of `line`. as in a
+// shell, a backslash only escapes the characters that are special there
+fn unquote_double(line: &str, start: usize) -> Result<(Vec<u8>, usize), SyntaxError> {
+    let mut bytes = Vec::new();
+    let offset = start + 1;
+    let mut chars = line[offset..].char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((bytes, offset + i + 1)),
+            '\\' => match chars.next() {
+                Some((_, c @ ('$' | '`' | '"' | '\\'))) => push_char(&mut bytes, c),
+                Some((_, c)) => {
+                    bytes.push(b'\\');
+                    push_char(&mut bytes, c);
+                }
+                None => break,
+            },
+            c => push_char(&mut bytes, c),
+        }
+    }
+
+    Err(SyntaxError::new(line, start, "unterminated \"...\" string"))
+}
+
+// decodes the shell word starting at byte `start` of `line`, which runs up
+// to the first unquoted whitespace, returning its bytes and where it ends
+fn decode_word(line: &str, start: usize) -> Result<(Vec<u8>, usize), SyntaxError> {
+    let mut bytes = Vec::new();
+    let mut index = start;
+
+    loop {
+        let rest = &line[index..];
+        if rest.starts_with(QUOTE_START) {
+            let (quoted, end) = unquote(line, index)?;
+            bytes.extend(quoted);
+            index = end;
+        } else if let Some(quoted) = rest.strip_prefix('\'') {
+            match quoted.find('\'') {
+                Some(len) => {
+                    bytes.extend_from_slice(&quoted.as_bytes()[..len]);
+                    index += len + 2;
+                }
+                None => return Err(SyntaxError::new(line, index, "unterminated '...' string")),
+            }
+        } else if rest.starts_with('"') {
+            let (quoted, end) = unquote_double(line, index)?;
+            bytes.extend(quoted);
+            index = end;
+        } else {
+            let mut chars = rest.chars();
+            match chars.next() {
+                None => break,
+                Some(c) if c.is_whitespace() => break,
+                Some('\\') => match chars.next() {
+                    Some(c) => {
+                        push_char(&mut bytes, c);
+                        index += 1 + c.len_utf8();
+                    }
+                    None => return Err(SyntaxError::new(line, index, "'\\' at end of line")),
+                },
+                Some(c) => {
+                    push_char(&mut bytes, c);
+                    index += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    Ok((bytes, index))
+}
+
+// only whitespace, or a shell comment after it, may follow a quoted string
+// that ends at byte `end`
+fn check_trailing(line: &str, end: usize, what: &str) -> Result<(), SyntaxError> {
+    let trailing = &line[end..];
+    match trailing.trim() {
+        "" => Ok(()),
+        comment if comment.starts_with(COMMENT) && trailing.starts_with(char::is_whitespace) => {
+            Ok(())
+        }
+        extra => {
+            let index = end + (trailing.len() - trailing.trim_start().len());
+            let msg = format!("unexpected '{}' after quoted {}", extra, what);
+            Err(SyntaxError::new(line, index, &msg))
+        }
+    }
+}
+
+pub fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with(COMMENT)
+}
+
+// puts a note about `msg` above `line` of the edit file, replacing any
+// notes left by earlier attempts. without a line it goes at the top
+pub fn insert_error_note(text: &[u8], line: Option<usize>, msg: &str) -> Vec<u8> {
+    let mut lines = Vec::new();
+    let mut target = 0;
+    for (index, l) in text.split(|b| *b == b'\n').enumerate() {
+        if Some(index + 1) == line {
+            target = lines.len();
+        }
+        if !l.starts_with(ERROR_NOTE.as_bytes()) {
+            lines.push(l);
+        }
+    }
+
+    let note = format!("{}{}", ERROR_NOTE, msg.replace('\n', " "));
+    lines.insert(target, note.as_bytes());
+    lines.join(&b'\n')
+}
+
+pub enum Line {
+    Var(Vec<u8>, Vec<u8>),
+    // `NAME=(`, followed by elements up to a line holding only `)`
+    ListStart(Vec<u8>),
+}
+
+// unquoted elements never hold '=', so inside a list block a line like this
+// means the block's closing `)` is missing rather than that it's an element
+pub fn is_assignment(line: &str) -> bool {
+    let line = line.trim();
+    !starts_quoted(line) && line.contains('=')
+}
+
+// returns None for the line that ends the list
+pub fn decode_element(line: &str) -> Result<Option<Vec<u8>>, SyntaxError> {
+    let element = line.trim();
+    if element == LIST_END {
+        return Ok(None);
+    }
+    if starts_quoted(element) {
+        let start = line.len() - line.trim_start().len();
+        let (element, end) = decode_word(line, start)?;
+        check_trailing(line, end, "element")?;
+        return Ok(Some(element));
+    }
+    Ok(Some(Vec::from(element.as_bytes())))
+}
+
+pub fn decode_line(line: &str) -> Result<Line, SyntaxError> {
+    let indent = line.len() - line.trim_start().len();
+    let start = match line[indent..].strip_prefix(EXPORT) {
+        Some(rest) if rest.starts_with(char::is_whitespace) => line.len() - rest.trim_start().len(),
+        _ => indent,
+    };
+
+    let (name, value_start) = if line[start..].starts_with(QUOTE_START) {
+        let (name, end) = unquote(line, start)?;
+        if !line[end..].starts_with('=') {
+            return Err(SyntaxError::new(
+                line,
+                end,
+                "expected '=' after quoted name",
+            ));
+        }
+        (name, end + 1)
+    } else {
+        match line[start..].find('=') {
+            Some(len) => (
+                Vec::from(&line.as_bytes()[start..start + len]),
+                start + len + 1,
+            ),
+            None => return Err(SyntaxError::new(line, line.len(), "missing '=' separator")),
+        }
+    };
+
+    let rest = &line[value_start..];
+    if rest.trim_end() == LIST_START {
+        return Ok(Line::ListStart(name));
+    }
+
+    let value = if starts_quoted(rest) {
+        let (value, end) = decode_word(line, value_start)?;
+        check_trailing(line, end, "value")?;
+        value
+    } else {
+        Vec::from(rest.as_bytes())
+    };
+
+    Ok(Line::Var(name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{
+        decode_element, decode_line, encode_line, encode_list, insert_error_note, is_assignment,
+        is_comment, Line, SyntaxError, HEADER,
+    };
+
+    fn decode_var(line: &str) -> Result<(Vec<u8>, Vec<u8>), SyntaxError> {
+        match decode_line(line)? {
+            Line::Var(name, value) => Ok((name, value)),
+            Line::ListStart(_) => panic!("unexpected list in {:?}", line),
+        }
+    }
+
+    fn round_trip(name: &[u8], value: &[u8]) -> String {
+        let line = encode_line(name, value);
+        assert!(!line.contains('\n'), "line break in {:?}", line);
+        let (decoded_name, decoded_value) = decode_var(&line).unwrap();
+        assert_eq!(decoded_name, name, "name of {:?}", line);
+        assert_eq!(decoded_value, value, "value of {:?}", line);
+        line
+    }
+
+    #[test]
+    fn plain_values_are_verbatim() {
+        assert_eq!(round_trip(b"KEY", b"VALUE"), "KEY=VALUE");
+        assert_eq!(round_trip(b"EMPTY", b""), "EMPTY=");
+        assert_eq!(round_trip(b"OPTS", b"a=b=c"), "OPTS=a=b=c");
+        assert_eq!(round_trip(b"SPACED", b"a b  c"), "SPACED=a b  c");
+        assert_eq!(round_trip(b"WIN", b"C:\\temp"), "WIN=C:\\temp");
+        assert_eq!(round_trip(b"Q", b"it's \"quoted\""), "Q=it's \"quoted\"");
+        assert_eq!(round_trip(b"DOLLAR", b"$HOME"), "DOLLAR=$HOME");
+        assert_eq!(
+            round_trip(b"UNICODE", "h\u{e9}llo".as_bytes()),
+            "UNICODE=h\u{e9}llo"
+        );
+    }
+
+    #[test]
+    fn special_values_round_trip() {
+        assert_eq!(
+            round_trip(b"MULTILINE", b"abc\ndef\n"),
+            "MULTILINE=$'abc\\ndef\\n'"
+        );
+        assert_eq!(round_trip(b"TAB", b"a\tb"), "TAB=$'a\\tb'");
+        assert_eq!(round_trip(b"LEADING", b"  x"), "LEADING=$'  x'");
+        assert_eq!(round_trip(b"TRAILING", b"x "), "TRAILING=$'x '");
+        assert_eq!(
+            round_trip(b"LOOKS_QUOTED", b"$'x'"),
+            "LOOKS_QUOTED=$'$\\'x\\''"
+        );
+        assert_eq!(
+            round_trip(b"BOTH", b" \\ 'x' \r"),
+            "BOTH=$' \\\\ \\'x\\' \\r'"
+        );
+        assert_eq!(round_trip(b"ESC", b"\x1b[0m"), "ESC=$'\\x1b[0m'");
+        assert_eq!(round_trip(b"HEX_NEXT", b"\x01a"), "HEX_NEXT=$'\\x01a'");
+        round_trip(b"CR_LF", b"\r\n");
+        round_trip(b"C1", "\u{85}".as_bytes());
+        round_trip(b"ONLY_SPACE", b" ");
+    }
+
+    #[test]
+    fn special_names_round_trip() {
+        assert_eq!(round_trip(b"A B", b"x"), "$'A B'=x");
+        assert_eq!(round_trip(b"A\nB", b"x\n"), "$'A\\nB'=$'x\\n'");
+        assert_eq!(round_trip(b"#X", b"x"), "$'#X'=x");
+        assert_eq!(round_trip(b"X#", b"#x"), "X#=#x");
+        round_trip(b"$'", b"");
+        round_trip(b"", b"");
+    }
+
+    #[test]
+    fn decode_escapes() {
+        let (_, value) = decode_var("X=$'\\a\\e\\E\\101\\x41\\x4\\?\\\"\\q'").unwrap();
+        assert_eq!(value, b"\x07\x1b\x1bAA\x04?\"\\q");
+
+        let (_, value) = decode_var("X=$'trailing space'   ").unwrap();
+        assert_eq!(value, b"trailing space");
+    }
+
+    #[test]
+    fn shell_forms() {
+        let forms = [
+            r#"FOO=it's "bar" \baz"#,
+            r#"export FOO=$'it\'s "bar" \\baz'"#,
+            r#"export  FOO='it'\''s "bar" \baz'"#,
+            r#"  export FOO='it'\''s "bar" \baz'"#,
+            r#"  FOO="it's \"bar\" \baz""#,
+            r#"FOO="it's \"bar\" \baz""#,
+            r#"FOO="it's "'"bar"'\ \\baz # comment"#,
+        ];
+        for form in forms {
+            let (name, value) = decode_var(form).unwrap();
+            assert_eq!(name, b"FOO", "name of {:?}", form);
+            assert_eq!(value, br#"it's "bar" \baz"#, "value of {:?}", form);
+        }
+
+        let (_, value) = decode_var(r#"X="\$HOME \a $'x'""#).unwrap();
+        assert_eq!(value, br#"$HOME \a $'x'"#);
+        let (name, _) = decode_var("exported=1").unwrap();
+        assert_eq!(name, b"exported");
+        assert!(matches!(
+            decode_line("export PATH=("),
+            Ok(Line::ListStart(_))
+        ));
+        assert!(matches!(
+            decode_line("  PATH=("),
+            Ok(Line::ListStart(name)) if name == b"PATH"
+        ));
+        assert_eq!(decode_element("  'a b'  ").unwrap(), Some(b"a b".to_vec()));
+
+        assert_eq!(round_trip(b"SINGLE", b"'x'"), "SINGLE=$'\\'x\\''");
+        assert_eq!(round_trip(b"DOUBLE", b"\"x\""), "DOUBLE=$'\"x\"'");
+    }
+
+    #[test]
+    fn decode_errors() {
+        let column = |line| decode_var(line).unwrap_err().column;
+        assert_eq!(column("NO_SEPARATOR"), 13);
+        assert_eq!(column("X=$'unterminated"), 3);
+        assert_eq!(column("X=$'ends in escape\\"), 3);
+        assert_eq!(column("X=$'a'  b"), 9);
+        assert_eq!(column("\u{e9}=$'\\xZZ'"), 5);
+        assert_eq!(column("$'X'Y=1"), 5);
+        assert_eq!(decode_element("  $'a' b").unwrap_err().column, 8);
+        assert_eq!(column("X='unterminated"), 3);
+        assert_eq!(column("X=\"a\\\""), 3);
+        assert_eq!(column("X='a'\\"), 6);
+    }
+
+    #[test]
+    fn list_blocks() {
+        let elements: [&[u8]; 7] = [b"/usr/bin", b"", b" padded ", b")", b"#x", b"/a b", b"A=("];
+        let block = encode_list(b"PATH", &elements);
+        assert_eq!(
+            block,
+            "PATH=(\n    /usr/bin\n    $''\n    $' padded '\n    $')'\n    $'#x'\n    /a b\n    \
+             $'A=('\n)"
+        );
+        assert!(block.lines().skip(1).all(|line| !is_assignment(line)));
+        assert!(is_assignment("  MANPATH=("));
+        assert!(is_assignment("A=1"));
+
+        let mut lines = block.lines();
+        match decode_line(lines.next().unwrap()).unwrap() {
+            Line::ListStart(name) => assert_eq!(name, b"PATH"),
+            Line::Var(..) => panic!("expected a list"),
+        }
+        let decoded: Vec<Option<Vec<u8>>> =
+            lines.map(|line| decode_element(line).unwrap()).collect();
+        let mut expected: Vec<Option<Vec<u8>>> =
+            elements.iter().map(|e| Some(e.to_vec())).collect();
+        expected.push(None);
+        assert_eq!(decoded, expected);
+
+        assert_eq!(round_trip(b"PAREN", b"("), "PAREN=$'('");
+        assert_eq!(decode_element("\t/bin  ").unwrap(), Some(b"/bin".to_vec()));
+        assert!(decode_element("$'x' y").is_err());
+    }
+
+    #[test]
+    fn comments() {
+        assert!(is_comment(""));
+        assert!(is_comment("   \t"));
+        assert!(is_comment("# note"));
+        assert!(is_comment("  #X=1"));
+        assert!(is_comment(HEADER.lines().next().unwrap()));
+        assert!(!is_comment("X=#1"));
+        assert!(!is_comment("=1"));
+    }
+
+    #[test]
+    fn error_notes() {
+        let text = b"A=1\nB\nC=3\n";
+        let noted = insert_error_note(text, Some(2), "missing '='");
+        assert_eq!(noted, b"A=1\n# envedit: missing '='\nB\nC=3\n");
+
+        // the old note is replaced, and line numbers count it
+        let renoted = insert_error_note(&noted, Some(4), "second");
+        assert_eq!(renoted, b"A=1\nB\n# envedit: second\nC=3\n");
+
+        assert_eq!(
+            insert_error_note(b"A=1\n", None, "x"),
+            b"# envedit: x\nA=1\n"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::config::Config;
+use std::ffi::{OsStr, OsString};
+
+// variables that are a ':' separated list of paths on every system we know of
+const BUILTIN_LISTS: [&str; 18] = [
+    "CDPATH",
+    "CLASSPATH",
+    "CPATH",
+    "C_INCLUDE_PATH",
+    "CPLUS_INCLUDE_PATH",
+    "DYLD_LIBRARY_PATH",
+    "FPATH",
+    "GOPATH",
+    "INFOPATH",
+    "LD_LIBRARY_PATH",
+    "LIBRARY_PATH",
+    "MANPATH",
+    "NODE_PATH",
+    "PATH",
+    "PERL5LIB",
+    "PKG_CONFIG_PATH",
+    "PYTHONPATH",
+    "XDG_DATA_DIRS",
+];
+
+pub const DEFAULT_SEPARATOR: &[u8] = b":";
+
+// the variables that are edited one element per line, and the separator
+// each of them is split on
+pub struct ListVars {
+    separators: Vec<(OsString, Vec<u8>)>,
+}
+
+impl ListVars {
+    pub fn none() -> ListVars {
+        ListVars {
+            separators: Vec::new(),
+        }
+    }
+
+    // config entries override the built-in list; an empty separator turns
+    // list editing off for that variable
+    pub fn new(config: &Config) -> ListVars {
+        let mut lists = ListVars::none();
+        for name in BUILTIN_LISTS {
+            lists.set(OsStr::new(name), DEFAULT_SEPARATOR);
+        }
+        for (name, separator) in &config.lists {
+            lists.set(OsStr::new(name), separator.as_bytes());
+        }
+        lists
+    }
+
+    fn set(&mut self, name: &OsStr, separator: &[u8]) {
+        self.separators.retain(|(n, _)| n != name);
+        if !separator.is_empty() {
+            self.separators
+                .push((name.to_os_string(), separator.to_vec()));
+        }
+    }
+
+    pub fn separator(&self, name: &OsStr) -> Option<&[u8]> {
+        self.separators
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, separator)| separator.as_slice())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ElementState {
+    Unchanged,
+    Added,
+    Removed,
+    // present on both sides, but not in the same order relative to the rest
+    Moved,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ElementDiff {
+    pub state: ElementState,
+    pub value: Vec<u8>,
+}
+
+// aligns the two lists on their longest common subsequence. anything left
+// over that appears on both sides was moved, and is reported once, at its
+// new position
+pub fn diff_elements(old: &[&[u8]], new: &[&[u8]]) -> Vec<ElementDiff> {
+    // lcs[i][j] is the length of the common subsequence of old[i..], new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut aligned = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            aligned.push((ElementState::Unchanged, new[j]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            aligned.push((ElementState::Added, new[j]));
+            j += 1;
+        } else {
+            aligned.push((ElementState::Removed, old[i]));
+            i += 1;
+        }
+    }
+
+    let mut removed: Vec<&[u8]> = aligned
+        .iter()
+        .filter(|(state, _)| *state == ElementState::Removed)
+        .map(|(_, value)| *value)
+        .collect();
+    let mut moved = Vec::new();
+    for (state, value) in aligned.iter_mut() {
+        if *state == ElementState::Added {
+            if let Some(index) = removed.iter().position(|r| r == value) {
+                removed.remove(index);
+                moved.push(*value);
+                *state = ElementState::Moved;
+            }
+        }
+    }
+
+    let mut elements = Vec::new();
+    for (state, value) in aligned {
+        if state == ElementState::Removed {
+            if let Some(index) = moved.iter().position(|m| *m == value) {
+                moved.remove(index);
+                continue;
+            }
+        }
+        elements.push(ElementDiff {
+            state,
+            value: value.to_vec(),
+        });
+    }
+    elements
+}
+
+pub fn split<'a>(value: &'a [u8], separator: &[u8]) -> Vec<&'a [u8]> {
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + separator.len() <= value.len() {
+        if &value[i..i + separator.len()] == separator {
+            elements.push(&value[start..i]);
+            i += separator.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    elements.push(&value[start..]);
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::list::{diff_elements, split, ElementState, ListVars};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn split_values() {
+        assert_eq!(split(b"/a:/b", b":"), [b"/a", b"/b"]);
+        assert_eq!(split(b"/a::/b:", b":"), [&b"/a"[..], b"", b"/b", b""]);
+        assert_eq!(split(b"", b":"), [b""]);
+        assert_eq!(split(b"a, b,c", b", "), [&b"a"[..], b"b,c"]);
+
+        let value = b"x;;y;z;";
+        assert_eq!(split(value, b";").join(&b";"[..]), value);
+    }
+
+    #[test]
+    fn separators_from_config() {
+        let mut config = Config::default();
+        config
+            .lists
+            .push((String::from("MY_FLAGS"), String::from(",")));
+        config.lists.push((String::from("CDPATH"), String::new()));
+        let lists = ListVars::new(&config);
+
+        assert_eq!(lists.separator(OsStr::new("PATH")), Some(&b":"[..]));
+        assert_eq!(lists.separator(OsStr::new("MY_FLAGS")), Some(&b","[..]));
+        assert_eq!(lists.separator(OsStr::new("CDPATH")), None);
+        assert_eq!(lists.separator(OsStr::new("HOME")), None);
+        assert_eq!(ListVars::none().separator(OsStr::new("PATH")), None);
+    }
+
+    fn states(old: &str, new: &str) -> Vec<(ElementState, String)> {
+        diff_elements(&split(old.as_bytes(), b":"), &split(new.as_bytes(), b":"))
+            .into_iter()
+            .map(|e| (e.state, String::from_utf8(e.value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn element_diffs() {
+        use ElementState::*;
+
+        assert_eq!(
+            states("/usr/bin:/bin", "/opt/foo/bin:/usr/bin:/bin"),
+            [
+                (Added, String::from("/opt/foo/bin")),
+                (Unchanged, String::from("/usr/bin")),
+                (Unchanged, String::from("/bin")),
+            ]
+        );
+        assert_eq!(
+            states("/a:/b:/c", "/a:/c"),
+            [
+                (Unchanged, String::from("/a")),
+                (Removed, String::from("/b")),
+                (Unchanged, String::from("/c"))
+            ]
+        );
+        assert_eq!(
+            states("/a:/b:/c", "/c:/a:/b"),
+            [
+                (Moved, String::from("/c")),
+                (Unchanged, String::from("/a")),
+                (Unchanged, String::from("/b"))
+            ]
+        );
+        assert_eq!(
+            states("/a:/b", "/b:/x"),
+            [
+                (Removed, String::from("/a")),
+                (Unchanged, String::from("/b")),
+                (Added, String::from("/x"))
+            ]
+        );
+    }
+}
@@ -1,80 +1,261 @@
-//use clap::{Command, Arg};
+mod config;
+mod editor;
+mod exec;
+mod filter;
+mod format;
+mod list;
+mod output;
+mod profile;
+mod shell;
+
+use clap::{Arg, ArgMatches};
+use config::Config;
+use editor::{Editor, Recovery};
+use filter::Filter;
+use format::Line;
+use list::{ElementDiff, ListVars};
+use shell::Shell;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::io::{self, BufRead, BufReader, Read, Seek, Write};
-use std::process::Command;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{self, ExitStatus};
+use std::time::SystemTime;
 use tempfile::NamedTempFile;
 
+const EXIT_STATUS_HELP: &str = "EXIT STATUS:
+    0    success
+    1    aborted after the edit file failed to parse
+    2    invalid arguments
+    3    no usable editor was found
+    4    the editor exited with an error, which cancels the edit
+    5    the edit file or profile could not be parsed
+    6    a variable name is not valid
+    7    an I/O error, such as a file that could not be read or written
+    8    the config file is invalid
+    9    the edit file was left unchanged, so there is nothing to apply
+    10   a sourced script exited before its changes could be captured
+With a COMMAND, its own exit status is passed on instead.";
+
+const EXIT_NO_CHANGES: i32 = 9;
+
+// each kind of error ends the program with its own exit status, as listed
+// in EXIT_STATUS_HELP
 #[derive(Debug)]
-struct EnvEditError {
-    msg: String,
+enum EnvEditError {
+    Aborted,
+    Usage(String),
+    EditorNotFound(String),
+    EditorFailed(ExitStatus),
+    ScriptFailed(ExitStatus),
+    Parse {
+        line: usize,
+        column: usize,
+        msg: String,
+    },
+    InvalidName {
+        name: OsString,
+        reason: String,
+        // the line of the edit file the name was on
+        line: Option<usize>,
+    },
+    Io {
+        context: String,
+        error: io::Error,
+    },
+    Config(String),
 }
 
-impl Error for EnvEditError {}
+impl Error for EnvEditError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EnvEditError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for EnvEditError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match self {
+            EnvEditError::Aborted => write!(f, "aborted; no changes were made"),
+            EnvEditError::Usage(msg) => write!(f, "{}", msg),
+            EnvEditError::EditorNotFound(msg) => write!(f, "{}", msg),
+            EnvEditError::EditorFailed(status) => {
+                match (status.code(), status.signal()) {
+                    (Some(code), _) => write!(f, "Editor exited with status {}", code)?,
+                    (None, Some(signal)) => write!(f, "Editor was killed by signal {}", signal)?,
+                    (None, None) => write!(f, "Editor failed")?,
+                }
+                write!(f, "; no changes were made")
+            }
+            EnvEditError::ScriptFailed(status) => write!(
+                f,
+                "Script exited ({}) before its changes could be captured",
+                status
+            ),
+            EnvEditError::Parse { line, column, msg } => write!(
+                f,
+                "Error reading file: line {}, column {}: {}",
+                line, column, msg
+            ),
+            EnvEditError::InvalidName { name, reason, line } => {
+                if let Some(line) = line {
+                    write!(f, "Error reading file: line {}: ", line)?;
+                }
+                write!(
+                    f,
+                    "Invalid variable name '{}': {}",
+                    name.to_string_lossy(),
+                    reason
+                )
+            }
+            EnvEditError::Io { context, error } => write!(f, "{}: {}", context, error),
+            EnvEditError::Config(msg) => write!(f, "{}", msg),
+        }
     }
 }
 
 impl EnvEditError {
-    fn new(msg: &str) -> EnvEditError {
-        EnvEditError {
-            msg: String::from(msg),
+    fn io(context: &str, error: io::Error) -> EnvEditError {
+        EnvEditError::Io {
+            context: String::from(context),
+            error,
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            EnvEditError::Aborted => 1,
+            EnvEditError::Usage(_) => 2,
+            EnvEditError::EditorNotFound(_) => 3,
+            EnvEditError::EditorFailed(_) => 4,
+            EnvEditError::Parse { .. } => 5,
+            EnvEditError::InvalidName { .. } => 6,
+            EnvEditError::Io { .. } => 7,
+            EnvEditError::Config(_) => 8,
+            EnvEditError::ScriptFailed(_) => 10,
+        }
+    }
+
+    // the line of the edit file to leave a note on, if the error has one
+    fn line(&self) -> Option<usize> {
+        match self {
+            EnvEditError::Parse { line, .. } => Some(*line),
+            EnvEditError::InvalidName { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    fn at_line(self, number: usize) -> EnvEditError {
+        match self {
+            EnvEditError::InvalidName { name, reason, .. } => EnvEditError::InvalidName {
+                name,
+                reason,
+                line: Some(number),
+            },
+            error => error,
         }
     }
 }
 
+#[derive(Clone)]
 struct EnvVar {
-    name: String,
-    value: String,
+    name: OsString,
+    value: OsString,
+}
+
+pub const NAME_CHECKS: [&str; 2] = ["kernel", "posix"];
+
+// how strictly variable names are checked
+#[derive(Clone, Copy)]
+enum NameCheck {
+    // anything the kernel can store in an environment
+    Kernel,
+    // only names a shell can export
+    Posix,
+}
+
+impl NameCheck {
+    fn from_name(name: &str) -> Option<NameCheck> {
+        match name {
+            "kernel" => Some(NameCheck::Kernel),
+            "posix" => Some(NameCheck::Posix),
+            _ => None,
+        }
+    }
 }
 
 impl EnvVar {
-    fn validate_name(name: &str) -> Result<(), EnvEditError> {
-        // the only restriction on environment variable names is that they
-        // cannot have '=' in them
-        match name.find('=') {
-            Some(_) => Err(EnvEditError::new(
-                "Variable name contains illegal character '='",
-            )),
-            None => Ok(()),
-        }
-    }
-
-    pub fn new(name: String, value: String) -> Result<EnvVar, EnvEditError> {
-        EnvVar::validate_name(name.as_str())?;
-        Ok(EnvVar {
-            name: name,
-            value: value,
+    fn validate_name(name: &OsStr, check: NameCheck) -> Result<(), EnvEditError> {
+        // the environment is a list of NUL-terminated `NAME=value` strings,
+        // so a name can hold anything else
+        let bytes = name.as_bytes();
+        let reason = if bytes.is_empty() {
+            "names cannot be empty"
+        } else if bytes.contains(&b'=') {
+            "names cannot contain '='"
+        } else if bytes.contains(&0) {
+            "names cannot contain NUL bytes"
+        } else if matches!(check, NameCheck::Posix) && !shell::is_exportable(name) {
+            shell::EXPORTABLE_RULE
+        } else {
+            return Ok(());
+        };
+        Err(EnvEditError::InvalidName {
+            name: name.to_os_string(),
+            reason: String::from(reason),
+            line: None,
         })
     }
+
+    pub fn new(name: OsString, value: OsString) -> Result<EnvVar, EnvEditError> {
+        EnvVar::checked(name, value, NameCheck::Kernel)
+    }
+
+    fn checked(name: OsString, value: OsString, check: NameCheck) -> Result<EnvVar, EnvEditError> {
+        EnvVar::validate_name(&name, check)?;
+        Ok(EnvVar { name, value })
+    }
 }
 
+#[derive(Clone)]
 struct EnvVars(Vec<EnvVar>);
 
 impl EnvVars {
     fn default() -> EnvVars {
-        EnvVars { 0: Vec::new() }
+        EnvVars(Vec::new())
     }
 
     fn insert(&mut self, var: EnvVar) {
         self.0.push(var);
     }
 
+    fn contains(&self, name: &OsStr) -> bool {
+        self.0.iter().any(|var| var.name == name)
+    }
+
+    fn retain<F: FnMut(&EnvVar) -> bool>(&mut self, f: F) {
+        self.0.retain(f);
+    }
+
     fn sort(&mut self) {
         self.0.sort_by(|a, b| a.name.cmp(&b.name));
     }
 }
 
-impl TryFrom<&mut dyn Iterator<Item = (String, String)>> for EnvVars {
+impl TryFrom<&mut dyn Iterator<Item = (OsString, OsString)>> for EnvVars {
     type Error = EnvEditError;
 
-    fn try_from(vars: &mut dyn Iterator<Item = (String, String)>) -> Result<Self, Self::Error> {
+    fn try_from(vars: &mut dyn Iterator<Item = (OsString, OsString)>) -> Result<Self, Self::Error> {
         let mut env_vars = EnvVars::default();
         for var in vars {
             let env_var = EnvVar::new(var.0, var.1)?;
@@ -86,39 +267,164 @@ impl TryFrom<&mut dyn Iterator<Item = (String, String)>> for EnvVars {
     }
 }
 
-impl TryFrom<&mut dyn Read> for EnvVars {
-    type Error = EnvEditError;
+// what to do when the edit file sets a variable more than once
+#[derive(Clone, Copy)]
+enum Duplicates {
+    Reject,
+    // keep the last assignment, with a warning
+    LastWins,
+}
 
-    fn try_from(file: &mut dyn Read) -> Result<Self, Self::Error> {
+impl EnvVars {
+    // `seen` holds the line each name was last set on
+    fn insert_from_line(
+        &mut self,
+        var: EnvVar,
+        line: usize,
+        seen: &mut HashMap<OsString, usize>,
+        duplicates: Duplicates,
+    ) -> Result<(), EnvEditError> {
+        if let Some(previous) = seen.insert(var.name.clone(), line) {
+            let name = var.name.to_string_lossy();
+            match duplicates {
+                Duplicates::Reject => {
+                    return Err(EnvEditError::Parse {
+                        line,
+                        column: 1,
+                        msg: format!("{} is already set on line {}", name, previous),
+                    })
+                }
+                Duplicates::LastWins => {
+                    eprintln!(
+                        "envedit: warning: {} is set on both line {} and line {}; using line {}",
+                        name, previous, line, line
+                    );
+                    self.retain(|v| v.name != var.name);
+                }
+            }
+        }
+        self.insert(var);
+        Ok(())
+    }
+
+    // list blocks are joined with the separator `lists` has for the
+    // variable, or ':' if it has none
+    fn parse(
+        file: &mut dyn Read,
+        lists: &ListVars,
+        duplicates: Duplicates,
+        names: NameCheck,
+    ) -> Result<EnvVars, EnvEditError> {
+        EnvVars::parse_edited(file, lists, duplicates, names, &EnvVars::default())
+    }
+
+    // like parse(), but names already in `original` only get the kernel
+    // check: they came from the environment rather than the user, and a
+    // shell that can't export them gets a warning instead
+    fn parse_edited(
+        file: &mut dyn Read,
+        lists: &ListVars,
+        duplicates: Duplicates,
+        names: NameCheck,
+        original: &EnvVars,
+    ) -> Result<EnvVars, EnvEditError> {
+        let check = |name: &OsStr| match original.contains(name) {
+            true => NameCheck::Kernel,
+            false => names,
+        };
         let mut env_vars = EnvVars::default();
+        let mut seen = HashMap::new();
+        // the name, elements so far and line number of an open list block
+        let mut list: Option<(OsString, Vec<Vec<u8>>, usize)> = None;
 
         let reader = BufReader::new(file);
         for (index, line) in reader.lines().enumerate() {
-            match line {
-                Ok(s) => {
-                    let v: Vec<&str> = s.split('=').collect();
-                    if v.len() < 2 {
-                        return Err(EnvEditError::new(&format!(
-                            "Error reading file: line {} is malformed; missing '=' separator",
-                            index
-                        )));
+            let number = index + 1;
+            let s = match line {
+                Ok(s) => s,
+                Err(e) => {
+                    return Err(EnvEditError::Parse {
+                        line: number,
+                        column: 1,
+                        msg: e.to_string(),
+                    })
+                }
+            };
+            let malformed = |e: format::SyntaxError| EnvEditError::Parse {
+                line: number,
+                column: e.column,
+                msg: e.msg,
+            };
+            let invalid = |e: EnvEditError| e.at_line(number);
+
+            if format::is_comment(&s) {
+                continue;
+            }
+
+            if let Some((name, mut elements, start)) = list.take() {
+                if format::is_assignment(&s) {
+                    return Err(missing_list_end(&name, start));
+                }
+                match format::decode_element(&s).map_err(malformed)? {
+                    Some(element) => {
+                        elements.push(element);
+                        list = Some((name, elements, start));
+                    }
+                    None => {
+                        let separator = lists.separator(&name).unwrap_or(list::DEFAULT_SEPARATOR);
+                        let value = OsString::from_vec(elements.join(separator));
+                        let check = check(&name);
+                        let var = EnvVar::checked(name, value, check).map_err(invalid)?;
+                        env_vars.insert_from_line(var, start, &mut seen, duplicates)?;
                     }
-                    let var = EnvVar::new(String::from(v[0]), String::from(v[1]))?;
-                    env_vars.insert(var);
                 }
-                Err(e) => {
-                    return Err(EnvEditError::new(
-                        format!("Error reading temp file: {}", e).as_str(),
-                    ))
+                continue;
+            }
+
+            match format::decode_line(&s).map_err(malformed)? {
+                Line::Var(name, value) => {
+                    let name = OsString::from_vec(name);
+                    let check = check(&name);
+                    let var =
+                        EnvVar::checked(name, OsString::from_vec(value), check).map_err(invalid)?;
+                    env_vars.insert_from_line(var, number, &mut seen, duplicates)?;
+                }
+                Line::ListStart(name) => {
+                    list = Some((OsString::from_vec(name), Vec::new(), number))
                 }
             }
         }
 
+        if let Some((name, _, start)) = list {
+            return Err(missing_list_end(&name, start));
+        }
+
         env_vars.sort();
         Ok(env_vars)
     }
 }
 
+fn missing_list_end(name: &OsStr, start: usize) -> EnvEditError {
+    EnvEditError::Parse {
+        line: start,
+        column: 1,
+        msg: format!("list {} is missing its closing ')'", name.to_string_lossy()),
+    }
+}
+
+impl TryFrom<&mut dyn Read> for EnvVars {
+    type Error = EnvEditError;
+
+    fn try_from(file: &mut dyn Read) -> Result<Self, Self::Error> {
+        EnvVars::parse(
+            file,
+            &ListVars::none(),
+            Duplicates::Reject,
+            NameCheck::Kernel,
+        )
+    }
+}
+
 impl IntoIterator for EnvVars {
     type Item = EnvVar;
     type IntoIter = std::vec::IntoIter<Self::Item>;
@@ -133,46 +439,71 @@ enum DiffState {
     Modified,
     Added,
     Deleted,
+    // deleted and added again under another name with the same value
+    Renamed,
 }
 
 struct DiffEntry {
-    name: String,
+    name: OsString,
+    // for renamed variables, the name it had before
+    old_name: Option<OsString>,
     state: DiffState,
-    old_value: Option<String>,
-    new_value: Option<String>,
+    old_value: Option<OsString>,
+    new_value: Option<OsString>,
+    // for modified list variables, how the elements changed
+    elements: Option<Vec<ElementDiff>>,
+}
+
+// whether diff() reports a variable deleted and another added with the same
+// value as a rename. that's a guess at what someone editing by hand meant,
+// so it's left out when the changes come from a script or a snapshot
+#[derive(Clone, Copy)]
+enum Renames {
+    Detect,
+    Ignore,
 }
 
-fn diff(old: EnvVars, new: EnvVars) -> Vec<DiffEntry> {
+fn diff(old: EnvVars, new: EnvVars, lists: &ListVars, renames: Renames) -> Vec<DiffEntry> {
     let mut map = HashMap::new();
 
     for var in new {
         let entry = DiffEntry {
-            name: String::from(&var.name),
+            name: var.name.clone(),
+            old_name: None,
             state: DiffState::Added,
             old_value: None,
-            new_value: Some(String::from(var.value)),
+            new_value: Some(var.value),
+            elements: None,
         };
-        map.insert(String::from(&var.name), entry);
+        map.insert(var.name, entry);
     }
 
     for var in old {
         match map.get_mut(&var.name) {
-            Some(mut entry) => {
-                entry.old_value = Some(String::from(&var.value));
+            Some(entry) => {
+                entry.old_value = Some(var.value.clone());
                 if var.value == entry.new_value.as_deref().unwrap() {
                     entry.state = DiffState::Unchanged;
                 } else {
                     entry.state = DiffState::Modified;
+                    if let Some(separator) = lists.separator(&var.name) {
+                        let old = list::split(var.value.as_bytes(), separator);
+                        let new_value = entry.new_value.as_deref().unwrap().as_bytes();
+                        let new = list::split(new_value, separator);
+                        entry.elements = Some(list::diff_elements(&old, &new));
+                    }
                 }
             }
             None => {
                 let entry = DiffEntry {
-                    name: String::from(&var.name),
+                    name: var.name.clone(),
+                    old_name: None,
                     state: DiffState::Deleted,
-                    old_value: Some(String::from(var.value)),
+                    old_value: Some(var.value),
                     new_value: None,
+                    elements: None,
                 };
-                map.insert(String::from(var.name), entry);
+                map.insert(var.name, entry);
             }
         }
     }
@@ -183,84 +514,841 @@ fn diff(old: EnvVars, new: EnvVars) -> Vec<DiffEntry> {
     }
 
     entries.sort_by(|a, b| a.name.cmp(&b.name));
-    entries
+    match renames {
+        Renames::Detect => detect_renames(entries),
+        Renames::Ignore => entries,
+    }
 }
 
-fn write_temp_file(vars: &EnvVars) -> io::Result<NamedTempFile> {
-    let mut file = NamedTempFile::new()?;
+// pairs each added entry with a deleted one holding the same value. empty
+// values are left alone, since they say nothing about where a variable
+// came from
+fn detect_renames(entries: Vec<DiffEntry>) -> Vec<DiffEntry> {
+    let mut deleted: Vec<&DiffEntry> = entries
+        .iter()
+        .filter(|e| matches!(e.state, DiffState::Deleted))
+        .filter(|e| !e.old_value.as_deref().unwrap().is_empty())
+        .collect();
+
+    let mut renames = Vec::new();
+    for entry in entries.iter() {
+        if !matches!(entry.state, DiffState::Added) {
+            continue;
+        }
+        let position = deleted.iter().position(|d| d.old_value == entry.new_value);
+        if let Some(position) = position {
+            renames.push((entry.name.clone(), deleted.remove(position).name.clone()));
+        }
+    }
+
+    let mut result = Vec::new();
+    for mut entry in entries {
+        if renames.iter().any(|(_, old_name)| *old_name == entry.name) {
+            continue;
+        }
+        if let Some((_, old_name)) = renames.iter().find(|(name, _)| *name == entry.name) {
+            entry.state = DiffState::Renamed;
+            entry.old_name = Some(old_name.clone());
+            entry.old_value = entry.new_value.clone();
+        }
+        result.push(entry);
+    }
+    result
+}
+
+// what the edit file looked like before the editor ran, to tell whether it
+// was touched at all
+#[derive(PartialEq)]
+struct FileState {
+    modified: SystemTime,
+    hash: u64,
+}
+
+impl FileState {
+    fn of(path: &Path) -> io::Result<FileState> {
+        let modified = fs::metadata(path)?.modified()?;
+        let mut hasher = DefaultHasher::new();
+        fs::read(path)?.hash(&mut hasher);
+        Ok(FileState {
+            modified,
+            hash: hasher.finish(),
+        })
+    }
+}
+
+fn write_vars(out: &mut dyn Write, vars: &EnvVars, lists: &ListVars) -> io::Result<()> {
     for var in vars.0.iter() {
-        writeln!(file, "{}={}", var.name, var.value)?;
+        let name = var.name.as_bytes();
+        let value = var.value.as_bytes();
+        match lists.separator(&var.name) {
+            Some(separator) if !value.is_empty() => {
+                let elements = list::split(value, separator);
+                writeln!(out, "{}", format::encode_list(name, &elements))?;
+            }
+            _ => writeln!(out, "{}", format::encode_line(name, value))?,
+        }
     }
+    Ok(())
+}
+
+fn write_temp_file(vars: &EnvVars, lists: &ListVars) -> io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    writeln!(file, "{}", format::HEADER)?;
+    write_vars(&mut file, vars, lists)?;
     file.flush()?;
     Ok(file)
 }
 
-fn main() {
-    let env_vars =
-        EnvVars::try_from(&mut env::vars() as &mut dyn Iterator<Item = (String, String)>)
-            .expect("Failed to load variables from environment");
+// `placeholders` are names that were asked for but aren't set; they are
+// offered as empty entries to fill in. returns None if the edit file was
+// left as it was written
+fn run_editor(
+    env_vars: &EnvVars,
+    placeholders: &[OsString],
+    config: &Config,
+    lists: &ListVars,
+    matches: &ArgMatches,
+) -> Result<Option<EnvVars>, EnvEditError> {
+    let editor = Editor::resolve(matches.value_of("editor"), config)?;
 
-    let mut file = write_temp_file(&env_vars).expect("FIXME");
-    let path = OsString::from(&file.path());
+    let to_edit = with_placeholders(env_vars, placeholders)?;
+    let file = write_temp_file(&to_edit, lists)
+        .map_err(|e| EnvEditError::io("Failed to write edit file", e))?;
+    let read_error = |e| EnvEditError::io("Failed to read edit file", e);
+    let duplicates = match matches.is_present("last-wins") {
+        true => Duplicates::LastWins,
+        false => Duplicates::Reject,
+    };
+    let names = NameCheck::from_name(matches.value_of("names").unwrap()).unwrap();
+    let original = FileState::of(file.path()).map_err(read_error)?;
 
-    let mut child = Command::new("nvim") // cspell:disable-line
-        .arg(path)
-        .arg("-c")
-        .arg("set filetype=sh")
-        .spawn()
-        .expect("what on earth");
+    let mut edited_env_vars = loop {
+        let mut command = editor.command(file.path());
+        if matches.is_present("emit") {
+            // stdout is captured by the calling shell's $(...), so the editor
+            // has to be given the terminal directly
+            let open_tty = || File::options().read(true).write(true).open("/dev/tty");
+            let tty_error = |e| EnvEditError::io("Failed to open /dev/tty", e);
+            command.stdin(open_tty().map_err(tty_error)?);
+            command.stdout(open_tty().map_err(tty_error)?);
+        }
+        let status = match command.status() {
+            Ok(status) => status,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(EnvEditError::EditorNotFound(format!(
+                    "Editor '{}' not found",
+                    editor.program()
+                )))
+            }
+            Err(e) => return Err(EnvEditError::io("Failed to start editor", e)),
+        };
+        // a failing editor is how `:cq` in vim cancels an edit
+        if !status.success() && !matches.is_present("ignore-editor-status") {
+            return Err(EnvEditError::EditorFailed(status));
+        }
+        if FileState::of(file.path()).map_err(read_error)? == original {
+            return Ok(None);
+        }
 
-    child.wait().expect("wait");
+        // many editors save by writing a new file and renaming it over the
+        // old one, so the edits have to be read back by path rather than
+        // through the handle we wrote with
+        let mut edited = File::open(file.path()).map_err(read_error)?;
+        let error = match EnvVars::parse_edited(&mut edited, lists, duplicates, names, env_vars) {
+            Ok(edited_env_vars) => break edited_env_vars,
+            Err(error) => error,
+        };
+        if !editor::can_ask() {
+            return Err(error);
+        }
 
-    file.rewind().expect("yup");
-    let edited_env_vars = EnvVars::try_from(&mut file as &mut dyn Read).expect("idk lol");
+        eprintln!("envedit: {}", error);
+        match editor::ask_recovery() {
+            Recovery::Edit => {
+                let text = fs::read(file.path()).map_err(read_error)?;
+                let noted = format::insert_error_note(&text, error.line(), &error.to_string());
+                fs::write(file.path(), noted)
+                    .map_err(|e| EnvEditError::io("Failed to write edit file", e))?;
+            }
+            Recovery::Discard => break env_vars.clone(),
+            Recovery::Abort => return Err(EnvEditError::Aborted),
+        }
+    };
+
+    drop_placeholders(&mut edited_env_vars, placeholders);
+    Ok(Some(edited_env_vars))
+}
 
-    let diff = diff(env_vars, edited_env_vars);
+// only the selected variables are edited and compared, so nothing filtered
+// out can show up as deleted. returns the names the filter asks for that
+// aren't set, to be offered as placeholders
+fn select(env_vars: &mut EnvVars, filter: &Filter) -> Vec<OsString> {
+    let mut placeholders = Vec::new();
+    if filter.is_empty() {
+        return placeholders;
+    }
+    env_vars.retain(|var| filter.matches(&var.name));
+    for name in filter.names() {
+        if !env_vars.contains(name) && !placeholders.contains(name) {
+            placeholders.push(name.clone());
+        }
+    }
+    placeholders
+}
+
+fn with_placeholders(
+    env_vars: &EnvVars,
+    placeholders: &[OsString],
+) -> Result<EnvVars, EnvEditError> {
+    let mut to_edit = env_vars.clone();
+    for name in placeholders {
+        to_edit.insert(EnvVar::new(name.clone(), OsString::new())?);
+    }
+    to_edit.sort();
+    Ok(to_edit)
+}
+
+// a placeholder that is still empty was never filled in, so it isn't an
+// addition
+fn drop_placeholders(env_vars: &mut EnvVars, placeholders: &[OsString]) {
+    env_vars.retain(|var| !(var.value.is_empty() && placeholders.contains(&var.name)));
+}
+
+fn build_filter(matches: &ArgMatches) -> Result<Filter, EnvEditError> {
+    let mut filter = Filter::default();
+    for name in matches.values_of_os("var").into_iter().flatten() {
+        filter.add_name(name);
+    }
+    for glob in matches.values_of_os("match").into_iter().flatten() {
+        filter.add_glob(glob);
+    }
+    for regex in matches.values_of("regex").into_iter().flatten() {
+        filter.add_regex(regex)?;
+    }
+    Ok(filter)
+}
+
+fn load_env() -> Result<EnvVars, EnvEditError> {
+    EnvVars::try_from(&mut env::vars_os() as &mut dyn Iterator<Item = (OsString, OsString)>)
+}
+
+fn load_lists(matches: &ArgMatches, config: &Config) -> ListVars {
+    match matches.is_present("no-lists") {
+        true => ListVars::none(),
+        false => ListVars::new(config),
+    }
+}
+
+fn stdout_error(error: io::Error) -> EnvEditError {
+    EnvEditError::io("Failed to write to stdout", error)
+}
+
+fn print_diff(diff: &[DiffEntry], matches: &ArgMatches) -> Result<(), EnvEditError> {
+    let result = match matches.value_of("format").unwrap() {
+        "json" => output::print_json(diff, matches.is_present("unchanged")),
+        _ => output::print_diff(
+            diff,
+            output::use_color(matches.value_of("color").unwrap()),
+            matches.value_of_t("context").unwrap_or_else(|e| e.exit()),
+            matches.is_present("unchanged"),
+        ),
+    };
+    result.map_err(stdout_error)
+}
+
+// applies `diff` the way the arguments ask, by writing a script, running a
+// command or printing the script or the diff, and returns the exit status
+// to end with
+fn finish(diff: &[DiffEntry], matches: &ArgMatches, emit: bool) -> Result<i32, EnvEditError> {
+    let shell = Shell::from_name(matches.value_of("shell").unwrap()).unwrap();
+
+    if emit || matches.is_present("emit-to") {
+        for name in shell::unexportable(diff) {
+            eprintln!(
+                "envedit: warning: the shell script can't change {}: {}; \
+                 pass it to a command with `envedit -- COMMAND` instead",
+                name.to_string_lossy(),
+                shell::EXPORTABLE_RULE
+            );
+        }
+    }
+
+    if let Some(emit_to) = matches.value_of_os("emit-to") {
+        // a command gets the edits instead of the calling shell, so the
+        // wrapper is left nothing to source
+        let script = match matches.is_present("command") {
+            true => Vec::new(),
+            false => shell::apply_script(diff, shell),
+        };
+        fs::write(emit_to, script).map_err(|e| EnvEditError::io("Failed to write script", e))?;
+    }
+
+    if let Some(mut command) = matches.values_of_os("command") {
+        let program = command.next().unwrap();
+        let args: Vec<&OsStr> = command.collect();
+        return Ok(exec::run(diff, program, &args));
+    }
+
+    if emit {
+        io::stdout()
+            .write_all(&shell::apply_script(diff, shell))
+            .map_err(|e| EnvEditError::io("Failed to write script", e))?;
+    } else {
+        print_diff(diff, matches)?;
+    }
+    Ok(0)
+}
+
+fn no_changes(matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    if let Some(emit_to) = matches.value_of_os("emit-to") {
+        fs::write(emit_to, shell::NO_CHANGES_NOTE)
+            .map_err(|e| EnvEditError::io("Failed to write script", e))?;
+    }
+    Ok(EXIT_NO_CHANGES)
+}
 
-    for entry in diff {
-        match entry.state {
-            DiffState::Added => {
-                println!("+ {}={}", entry.name, entry.new_value.unwrap());
+// returns the exit status to end with
+fn edit(matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    let mut env_vars = load_env()?;
+    let config = Config::load()?;
+    let lists = load_lists(matches, &config);
+
+    let edited_env_vars = match matches.value_of_os("profile") {
+        Some(profile) => {
+            // a profile only sets the variables it names, so everything else
+            // is left out of the comparison rather than seen as deleted
+            let profile = parse_profile(&profile::read_name_or_path(profile)?, &lists)?;
+            env_vars.retain(|var| profile.contains(&var.name));
+            profile
+        }
+        None => {
+            let placeholders = select(&mut env_vars, &build_filter(matches)?);
+            match run_editor(&env_vars, &placeholders, &config, &lists, matches)? {
+                Some(edited_env_vars) => edited_env_vars,
+                // the command is still run, just with the environment as it was
+                None if matches.is_present("command") => env_vars.clone(),
+                None => return no_changes(matches),
+            }
+        }
+    };
+
+    let diff = diff(env_vars, edited_env_vars, &lists, Renames::Detect);
+    finish(&diff, matches, matches.is_present("emit"))
+}
+
+fn parse_profile(text: &[u8], lists: &ListVars) -> Result<EnvVars, EnvEditError> {
+    EnvVars::parse(&mut &text[..], lists, Duplicates::Reject, NameCheck::Kernel)
+}
+
+// snapshots and profiles are written without list blocks, so reading one back
+// doesn't depend on the separators configured at the time
+fn write_snapshot(env_vars: &EnvVars) -> Result<Vec<u8>, EnvEditError> {
+    let mut text = Vec::new();
+    write_vars(&mut text, env_vars, &ListVars::none())
+        .map_err(|e| EnvEditError::io("Failed to write snapshot", e))?;
+    Ok(text)
+}
+
+fn read_snapshot(text: &[u8]) -> Result<EnvVars, EnvEditError> {
+    EnvVars::parse(
+        &mut &text[..],
+        &ListVars::none(),
+        Duplicates::Reject,
+        NameCheck::Kernel,
+    )
+}
+
+fn snapshot(sub_matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    let path = sub_matches.value_of_os("file").unwrap();
+    fs::write(path, write_snapshot(&load_env()?)?).map_err(|e| {
+        let context = format!("Failed to write snapshot {}", path.to_string_lossy());
+        EnvEditError::io(&context, e)
+    })?;
+    Ok(0)
+}
+
+fn diff_since(matches: &ArgMatches, sub_matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    let path = sub_matches.value_of_os("since").unwrap();
+    let text = fs::read(path).map_err(|e| {
+        let context = format!("Failed to read snapshot {}", path.to_string_lossy());
+        EnvEditError::io(&context, e)
+    })?;
+    let snapshot = read_snapshot(&text)?;
+
+    let lists = load_lists(matches, &Config::load()?);
+    let diff = diff(snapshot, load_env()?, &lists, Renames::Ignore);
+    print_diff(&diff, matches)?;
+    Ok(0)
+}
+
+fn parse_env0(text: &[u8]) -> Result<EnvVars, EnvEditError> {
+    let mut vars = text.split(|b| *b == 0).filter_map(|entry| {
+        let separator = entry.iter().position(|b| *b == b'=')?;
+        let name = OsString::from_vec(entry[..separator].to_vec());
+        let value = OsString::from_vec(entry[separator + 1..].to_vec());
+        Some((name, value))
+    });
+    EnvVars::try_from(&mut vars as &mut dyn Iterator<Item = (OsString, OsString)>)
+}
+
+fn source(matches: &ArgMatches, sub_matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    let script = sub_matches.value_of_os("script").unwrap();
+    let args: Vec<&OsStr> = sub_matches
+        .values_of_os("args")
+        .into_iter()
+        .flatten()
+        .collect();
+    let captured = exec::capture(sub_matches.value_of_os("with").unwrap(), script, &args)?;
+    if !captured.status.success() {
+        eprintln!(
+            "envedit: warning: {} returned {}",
+            script.to_string_lossy(),
+            captured.status
+        );
+    }
+
+    let lists = load_lists(matches, &Config::load()?);
+    let before = parse_env0(&captured.before)?;
+    let after = parse_env0(&captured.after)?;
+    let diff = diff(before, after, &lists, Renames::Ignore);
+
+    if let Some(name) = sub_matches.value_of("save-profile") {
+        // a profile can only set variables, so anything the script unset
+        // is left out
+        let mut vars = EnvVars::default();
+        for entry in diff.iter() {
+            let unset = match entry.state {
+                DiffState::Added | DiffState::Modified => None,
+                DiffState::Renamed => entry.old_name.as_ref(),
+                DiffState::Deleted => Some(&entry.name),
+                DiffState::Unchanged => continue,
+            };
+            if let Some(unset) = unset {
+                eprintln!(
+                    "envedit: warning: profile '{}' can't record that {} was unset",
+                    name,
+                    unset.to_string_lossy()
+                );
             }
-            DiffState::Deleted => {
-                println!("- {}={}", entry.name, entry.old_value.unwrap());
+            if let Some(value) = &entry.new_value {
+                vars.insert(EnvVar::new(entry.name.clone(), value.clone())?);
             }
-            DiffState::Modified => {
-                println!("- {}={}", entry.name, entry.old_value.unwrap());
-                println!("+ {}={}", entry.name, entry.new_value.unwrap());
+        }
+
+        let mut text = Vec::new();
+        write_vars(&mut text, &vars, &ListVars::none())
+            .map_err(|e| EnvEditError::io("Failed to write profile", e))?;
+        profile::write(name, &text)?;
+    }
+
+    finish(&diff, matches, matches.is_present("emit"))
+}
+
+// `matches` are the top-level arguments, which the profile commands share
+// with editing
+fn profile(matches: &ArgMatches, sub_matches: &ArgMatches) -> Result<i32, EnvEditError> {
+    let config = Config::load()?;
+    let lists = load_lists(matches, &config);
+
+    match sub_matches.subcommand() {
+        Some(("save", save_matches)) => {
+            let name = save_matches.value_of("name").unwrap();
+            let mut env_vars = load_env()?;
+            let filter = build_filter(save_matches)?;
+            if !filter.is_empty() {
+                env_vars.retain(|var| filter.matches(&var.name));
             }
-            DiffState::Unchanged => {
-                println!("  {}={}", entry.name, entry.new_value.unwrap());
+
+            let mut text = Vec::new();
+            write_vars(&mut text, &env_vars, &ListVars::none())
+                .map_err(|e| EnvEditError::io("Failed to write profile", e))?;
+            profile::write(name, &text)?;
+            let count = env_vars.0.len();
+            let plural = if count == 1 { "" } else { "s" };
+            eprintln!(
+                "envedit: saved {} variable{} to profile '{}'",
+                count, plural, name
+            );
+            Ok(0)
+        }
+        Some(("load", load_matches)) => {
+            let name = load_matches.value_of("name").unwrap();
+            let profile = parse_profile(&profile::read_existing(name)?, &lists)?;
+            let mut env_vars = load_env()?;
+            env_vars.retain(|var| profile.contains(&var.name));
+
+            // the script goes to stdout unless the shell wrapper asked for it
+            // in a file, in which case the diff is shown instead
+            let emit = matches.is_present("emit") || !matches.is_present("emit-to");
+            let diff = diff(env_vars, profile, &lists, Renames::Ignore);
+            finish(&diff, matches, emit)
+        }
+        Some(("edit", edit_matches)) => {
+            let name = edit_matches.value_of("name").unwrap();
+            // editing a profile that doesn't exist yet creates it
+            let text = profile::read(name)?.unwrap_or_default();
+            let env_vars = parse_profile(&text, &lists)?;
+            let edited_env_vars = match run_editor(&env_vars, &[], &config, &lists, matches)? {
+                Some(edited_env_vars) => edited_env_vars,
+                None => return no_changes(matches),
+            };
+
+            let mut text = Vec::new();
+            write_vars(&mut text, &edited_env_vars, &ListVars::none())
+                .map_err(|e| EnvEditError::io("Failed to write profile", e))?;
+            profile::write(name, &text)?;
+            let diff = diff(env_vars, edited_env_vars, &lists, Renames::Detect);
+            print_diff(&diff, matches)?;
+            Ok(0)
+        }
+        Some(("list", _)) => {
+            let mut out = io::stdout().lock();
+            for name in profile::list()? {
+                writeln!(out, "{}", name).map_err(stdout_error)?;
             }
+            Ok(0)
         }
+        Some(("rm", rm_matches)) => {
+            profile::remove(rm_matches.value_of("name").unwrap())?;
+            Ok(0)
+        }
+        _ => unreachable!(),
     }
+}
 
-    // let matches = Command::new("envedit")
-    //     .arg(Arg::new("var")
-    //         .required(false)
-    //         .help("name of environment variable to edit")
-    //         .multiple_occurrences(true))
-    //     .get_matches();
+fn profile_name_arg() -> Arg<'static> {
+    Arg::new("name")
+        .required(true)
+        .value_name("NAME")
+        .help("name of the profile")
+}
 
-    // if let Some(var) = matches.value_of("var") {
-    //     println!("var = '{}'", var);
-    // }
-    // let output = Command::new("env").output().expect("msg");
-    // println!("{}", output.stdout);
+fn main() {
+    let matches = clap::Command::new("envedit")
+        .after_help(EXIT_STATUS_HELP)
+        .arg(
+            Arg::new("emit")
+                .long("emit")
+                .global(true)
+                .help("print shell code that applies the edits instead of the diff"),
+        )
+        .arg(
+            Arg::new("emit-to")
+                .long("emit-to")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("also write shell code that applies the edits to FILE"),
+        )
+        .arg(
+            Arg::new("editor")
+                .long("editor")
+                .global(true)
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("editor to use instead of $VISUAL or $EDITOR"),
+        )
+        .arg(
+            Arg::new("ignore-editor-status")
+                .long("ignore-editor-status")
+                .global(true)
+                .help("use the edits even if the editor exits with an error"),
+        )
+        .arg(
+            Arg::new("last-wins")
+                .long("last-wins")
+                .global(true)
+                .help("use the last of several lines setting a variable instead of failing"),
+        )
+        .arg(
+            Arg::new("names")
+                .long("names")
+                .global(true)
+                .takes_value(true)
+                .value_name("CHECK")
+                .possible_values(NAME_CHECKS)
+                .default_value("kernel")
+                .help("how strictly to check names added in the edit file"),
+        )
+        .arg(
+            Arg::new("no-lists")
+                .long("no-lists")
+                .global(true)
+                .help("edit list variables such as PATH on a single line"),
+        )
+        .arg(
+            Arg::new("no-edit")
+                .long("no-edit")
+                .requires("profile")
+                .help("apply the profile without opening an editor"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("NAME|FILE")
+                .allow_invalid_utf8(true)
+                .requires("no-edit")
+                .help("saved profile or file of variables to apply instead of editing"),
+        )
+        .arg(
+            Arg::new("var")
+                .required(false)
+                .value_name("VAR")
+                .multiple_values(true)
+                .allow_invalid_utf8(true)
+                .conflicts_with("profile")
+                .help("name of environment variable to edit"),
+        )
+        .arg(
+            Arg::new("match")
+                .long("match")
+                .takes_value(true)
+                .value_name("GLOB")
+                .multiple_occurrences(true)
+                .allow_invalid_utf8(true)
+                .conflicts_with("profile")
+                .help("edit variables whose names match GLOB"),
+        )
+        .arg(
+            Arg::new("regex")
+                .long("regex")
+                .takes_value(true)
+                .value_name("REGEX")
+                .multiple_occurrences(true)
+                .conflicts_with("profile")
+                .help("edit variables whose names match REGEX"),
+        )
+        .arg(
+            Arg::new("command")
+                .last(true)
+                .multiple_values(true)
+                .allow_invalid_utf8(true)
+                .value_name("COMMAND")
+                .help("run COMMAND with the edited environment instead of printing the diff"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(output::FORMATS)
+                .default_value("text")
+                .help("format of the diff output"),
+        )
+        .arg(
+            Arg::new("unchanged")
+                .long("unchanged")
+                .global(true)
+                .help("include all unchanged variables in the output"),
+        )
+        .arg(
+            Arg::new("context")
+                .long("context")
+                .global(true)
+                .short('U')
+                .takes_value(true)
+                .value_name("N")
+                .default_value("0")
+                .help("show N unchanged variables around each change"),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .global(true)
+                .takes_value(true)
+                .value_name("WHEN")
+                .possible_values(output::COLOR_CHOICES)
+                .default_value("auto")
+                .help("color the diff output"),
+        )
+        .arg(
+            Arg::new("shell")
+                .long("shell")
+                .global(true)
+                .takes_value(true)
+                .possible_values(shell::SHELL_NAMES)
+                .default_value("sh")
+                .help("shell syntax used for the emitted code"),
+        )
+        .subcommand(
+            clap::Command::new("init")
+                .about("print a shell function that applies edits to the current shell")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .possible_values(["bash", "zsh", "fish"]),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("snapshot")
+                .about("save the whole environment to a file to diff against later")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .value_name("FILE")
+                        .allow_invalid_utf8(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("diff")
+                .about("show how the environment changed since a snapshot")
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .allow_invalid_utf8(true)
+                        .help("snapshot written by `envedit snapshot`"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("source")
+                .about("show how sourcing a script changes the environment")
+                .trailing_var_arg(true)
+                .arg(
+                    Arg::new("with")
+                        .long("with")
+                        .takes_value(true)
+                        .value_name("SHELL")
+                        .allow_invalid_utf8(true)
+                        .default_value("sh")
+                        .help("shell to source the script with"),
+                )
+                .arg(
+                    Arg::new("save-profile")
+                        .long("save-profile")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .help("also save the variables the script sets as a profile"),
+                )
+                .arg(
+                    Arg::new("script")
+                        .required(true)
+                        .value_name("SCRIPT")
+                        .allow_invalid_utf8(true),
+                )
+                .arg(
+                    Arg::new("args")
+                        .multiple_values(true)
+                        .allow_hyphen_values(true)
+                        .value_name("ARGS")
+                        .allow_invalid_utf8(true)
+                        .help("arguments passed to the script"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("profile")
+                .about("save, load and edit named sets of variables")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("save")
+                        .about("save variables from the environment as a profile")
+                        .arg(profile_name_arg())
+                        .arg(
+                            Arg::new("var")
+                                .required(false)
+                                .value_name("VAR")
+                                .multiple_values(true)
+                                .allow_invalid_utf8(true)
+                                .help("name of environment variable to save"),
+                        )
+                        .arg(
+                            Arg::new("match")
+                                .long("match")
+                                .takes_value(true)
+                                .value_name("GLOB")
+                                .multiple_occurrences(true)
+                                .allow_invalid_utf8(true)
+                                .help("save variables whose names match GLOB"),
+                        )
+                        .arg(
+                            Arg::new("regex")
+                                .long("regex")
+                                .takes_value(true)
+                                .value_name("REGEX")
+                                .multiple_occurrences(true)
+                                .help("save variables whose names match REGEX"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("load")
+                        .about("print shell code that applies a profile")
+                        .arg(profile_name_arg()),
+                )
+                .subcommand(
+                    clap::Command::new("edit")
+                        .about("edit a profile, creating it if needed")
+                        .arg(profile_name_arg()),
+                )
+                .subcommand(clap::Command::new("list").about("list saved profiles"))
+                .subcommand(
+                    clap::Command::new("rm")
+                        .about("delete a profile")
+                        .arg(profile_name_arg()),
+                ),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("init", sub_matches)) => {
+            let shell = Shell::from_name(sub_matches.value_of("shell").unwrap()).unwrap();
+            io::stdout()
+                .write_all(shell::wrapper(shell).as_bytes())
+                .map_err(stdout_error)
+                .map(|()| 0)
+        }
+        Some(("profile", sub_matches)) => profile(&matches, sub_matches),
+        Some(("snapshot", sub_matches)) => snapshot(sub_matches),
+        Some(("diff", sub_matches)) => diff_since(&matches, sub_matches),
+        Some(("source", sub_matches)) => source(&matches, sub_matches),
+        _ => edit(&matches),
+    };
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            // a reader such as `head` closing the pipe early isn't worth a
+            // message
+            let broken_pipe = matches!(
+                &error,
+                EnvEditError::Io { error, .. } if error.kind() == io::ErrorKind::BrokenPipe
+            );
+            if !broken_pipe {
+                eprintln!("envedit: {}", error);
+            }
+            process::exit(error.exit_code());
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::EnvVars;
+    use crate::config::Config;
+    use crate::filter::Filter;
+    use crate::format;
+    use crate::list::ListVars;
+    use crate::{
+        diff, drop_placeholders, parse_env0, parse_profile, read_snapshot, select,
+        with_placeholders, write_snapshot, write_temp_file, write_vars, DiffEntry, DiffState,
+        Duplicates, EnvEditError, EnvVars, FileState, NameCheck, Renames,
+    };
+    use std::ffi::{OsStr, OsString};
+    use std::fs::{self, File};
+    use std::os::unix::ffi::OsStringExt;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
 
     #[test]
     fn env_vars_values() {
         let values = vec![
-            (String::from("KEY"), String::from("VALUE")),
-            (String::from("MULTILINE"), String::from("abc\ndef\n")),
+            (OsString::from("KEY"), OsString::from("VALUE")),
+            (OsString::from("MULTILINE"), OsString::from("abc\ndef\n")),
         ];
         let result = EnvVars::try_from(
-            &mut values.into_iter() as &mut dyn Iterator<Item = (String, String)>
+            &mut values.into_iter() as &mut dyn Iterator<Item = (OsString, OsString)>
         )
         .unwrap();
 
@@ -272,4 +1360,374 @@ mod tests {
         assert_eq!(result.0[1].name, "MULTILINE");
         assert_eq!(result.0[1].value, "abc\ndef\n");
     }
+
+    #[test]
+    fn env_vars_temp_file_round_trip() {
+        let values = vec![
+            (OsString::from("EQUALS"), OsString::from("a=b")),
+            (OsString::from("MULTILINE"), OsString::from("abc\ndef\n")),
+            (OsString::from("PATH"), OsString::from("/usr/bin::/a b:(:")),
+            (
+                OsString::from("QUOTES"),
+                OsString::from("'single' \"double\""),
+            ),
+            (OsString::from("WHITESPACE"), OsString::from(" \tpadded\t ")),
+            (
+                OsString::from("Z_LATIN1"),
+                OsString::from_vec(b"caf\xe9".to_vec()),
+            ),
+        ];
+        let env_vars = EnvVars::try_from(
+            &mut values.clone().into_iter() as &mut dyn Iterator<Item = (OsString, OsString)>
+        )
+        .unwrap();
+
+        let lists = ListVars::new(&Config::default());
+        let file = write_temp_file(&env_vars, &lists).unwrap();
+        let mut reopened = File::open(file.path()).unwrap();
+        let result =
+            EnvVars::parse(&mut reopened, &lists, Duplicates::Reject, NameCheck::Kernel).unwrap();
+
+        let result: Vec<(OsString, OsString)> = result
+            .into_iter()
+            .map(|var| (var.name, var.value))
+            .collect();
+        assert_eq!(result, values);
+    }
+
+    fn parse(
+        text: &str,
+        duplicates: Duplicates,
+        names: NameCheck,
+    ) -> Result<Vec<(OsString, OsString)>, EnvEditError> {
+        let result = EnvVars::parse(&mut text.as_bytes(), &ListVars::none(), duplicates, names)?;
+        Ok(result
+            .into_iter()
+            .map(|var| (var.name, var.value))
+            .collect())
+    }
+
+    #[test]
+    fn parse_skips_comments() {
+        let text = "# header\n\nA=1\n  # indented\nPATH=(\n\n    # note\n    /bin\n)\n";
+        let result = parse(text, Duplicates::Reject, NameCheck::Kernel).unwrap();
+        assert_eq!(
+            result,
+            [
+                (OsString::from("A"), OsString::from("1")),
+                (OsString::from("PATH"), OsString::from("/bin"))
+            ]
+        );
+    }
+
+    #[test]
+    fn env0_output() {
+        let result = parse_env0(b"B=x=y\0A=multi\nline\0NO_SEPARATOR\0\0").unwrap();
+        let result: Vec<(OsString, OsString)> = result
+            .into_iter()
+            .map(|var| (var.name, var.value))
+            .collect();
+        assert_eq!(
+            result,
+            [
+                (OsString::from("A"), OsString::from("multi\nline")),
+                (OsString::from("B"), OsString::from("x=y"))
+            ]
+        );
+    }
+
+    #[test]
+    fn file_state_tracks_contents() {
+        let file = write_temp_file(&env_vars(&[("A", "1")]), &ListVars::none()).unwrap();
+        let original = FileState::of(file.path()).unwrap();
+        assert!(FileState::of(file.path()).unwrap() == original);
+
+        fs::write(file.path(), "A=2\n").unwrap();
+        assert!(FileState::of(file.path()).unwrap() != original);
+    }
+
+    #[test]
+    fn editor_failures() {
+        let error = EnvEditError::EditorFailed(ExitStatus::from_raw(1 << 8));
+        assert_eq!(
+            error.to_string(),
+            "Editor exited with status 1; no changes were made"
+        );
+        let error = EnvEditError::EditorFailed(ExitStatus::from_raw(9));
+        assert_eq!(
+            error.to_string(),
+            "Editor was killed by signal 9; no changes were made"
+        );
+        assert_eq!(error.exit_code(), 4);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |text| {
+            parse(text, Duplicates::Reject, NameCheck::Kernel)
+                .err()
+                .unwrap()
+        };
+
+        let error = parse("A=1\nB=$'\\xZZ'\n");
+        assert!(matches!(
+            error,
+            EnvEditError::Parse {
+                line: 2,
+                column: 5,
+                ..
+            }
+        ));
+        assert_eq!(error.exit_code(), 5);
+
+        let error = parse("A=1\nB=2\n$'B=C'=1\n");
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(error.exit_code(), 6);
+
+        let error = parse("PATH=(\n    /bin\n");
+        assert_eq!(error.line(), Some(1));
+
+        // an assignment inside a block means its `)` is missing
+        for text in [
+            "PATH=(\n  /bin\nMANPATH=(\n  /usr/man\n)\n",
+            "A=1\nPATH=(\n  /bin\nB=2\n",
+        ] {
+            let error = parse(text);
+            assert_eq!(error.exit_code(), 5);
+            assert!(error
+                .to_string()
+                .contains("list PATH is missing its closing ')'"));
+            assert_eq!(
+                error.line(),
+                Some(text.lines().position(|l| l == "PATH=(").unwrap() + 1)
+            );
+        }
+
+        let error = parse("A=1\nPATH=(\n    /bin\n)\nA=2\nPATH=/usr/bin\n");
+        assert_eq!(error.line(), Some(5));
+        assert!(error.to_string().ends_with("A is already set on line 1"));
+    }
+
+    #[test]
+    fn name_checks() {
+        let check = |text, names| match parse(text, Duplicates::Reject, names) {
+            Ok(_) => None,
+            Err(EnvEditError::InvalidName { reason, .. }) => Some(reason),
+            Err(error) => panic!("unexpected error {}", error),
+        };
+
+        assert_eq!(
+            check("=1", NameCheck::Kernel).unwrap(),
+            "names cannot be empty"
+        );
+        assert!(check("$'A\\x00B'=1", NameCheck::Kernel).is_some());
+        assert!(check("$'A B'=1\n1A=1\nA.B=1", NameCheck::Kernel).is_none());
+
+        assert!(check("_A1=1\nb=2", NameCheck::Posix).is_none());
+        assert!(check("$'A B'=1", NameCheck::Posix).is_some());
+        assert!(check("1A=1", NameCheck::Posix).is_some());
+        assert!(check("$'caf\\xe9'=1", NameCheck::Posix).is_some());
+
+        // names that were already set aren't held to the posix rule
+        let original = env_vars(&[("BASH_FUNC_f%%", "() { :; }"), ("A", "1")]);
+        let parse_edited = |text: &str| {
+            EnvVars::parse_edited(
+                &mut text.as_bytes(),
+                &ListVars::none(),
+                Duplicates::Reject,
+                NameCheck::Posix,
+                &original,
+            )
+        };
+        assert!(parse_edited("BASH_FUNC_f%%=() { :; }\nA=2\nB=3").is_ok());
+        assert_eq!(parse_edited("A=1\nA.B=1").err().unwrap().line(), Some(2));
+        assert!(parse_edited("$'BASH_FUNC_g%%'=1").is_err());
+    }
+
+    #[test]
+    fn parse_last_wins() {
+        let text = "A=1\nPATH=(\n    /bin\n)\nA=2\nPATH=/usr/bin\n";
+        let result = parse(text, Duplicates::LastWins, NameCheck::Kernel).unwrap();
+        assert_eq!(
+            result,
+            [
+                (OsString::from("A"), OsString::from("2")),
+                (OsString::from("PATH"), OsString::from("/usr/bin"))
+            ]
+        );
+    }
+
+    fn env_vars(values: &[(&str, &str)]) -> EnvVars {
+        let mut values = values
+            .iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)));
+        EnvVars::try_from(&mut values as &mut dyn Iterator<Item = (OsString, OsString)>).unwrap()
+    }
+
+    #[test]
+    fn diff_detects_renames() {
+        let old = env_vars(&[("EMPTY", ""), ("FOO", "value"), ("KEEP", "value")]);
+        let new = env_vars(&[("BAR", "value"), ("KEEP", "value"), ("NEW_EMPTY", "")]);
+        let result = diff(old, new, &ListVars::none(), Renames::Detect);
+
+        let states: Vec<(&str, &DiffState)> = result
+            .iter()
+            .map(|e| (e.name.to_str().unwrap(), &e.state))
+            .collect();
+        assert!(matches!(
+            states[..],
+            [
+                ("BAR", DiffState::Renamed),
+                ("EMPTY", DiffState::Deleted),
+                ("KEEP", DiffState::Unchanged),
+                ("NEW_EMPTY", DiffState::Added)
+            ]
+        ));
+        assert_eq!(result[0].old_name.as_deref().unwrap(), "FOO");
+        assert_eq!(result[0].old_value.as_deref().unwrap(), "value");
+
+        let old = env_vars(&[("A", "1")]);
+        let new = env_vars(&[("NEW", "1")]);
+        let result = diff(old, new, &ListVars::none(), Renames::Ignore);
+        assert!(matches!(
+            result[..],
+            [
+                DiffEntry {
+                    state: DiffState::Deleted,
+                    ..
+                },
+                DiffEntry {
+                    state: DiffState::Added,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn profiles_ignore_list_separators() {
+        let vars = env_vars(&[("PATH", "/a:/b;/c"), ("PLAIN", "x")]);
+        let mut text = Vec::new();
+        write_vars(&mut text, &vars, &ListVars::none()).unwrap();
+
+        // the separator configured when loading may differ from the one in
+        // effect when the profile was saved
+        let config = Config {
+            editor: None,
+            lists: vec![("PATH".to_string(), ";".to_string())],
+        };
+        for lists in [
+            ListVars::none(),
+            ListVars::new(&Config::default()),
+            ListVars::new(&config),
+        ] {
+            let profile = parse_profile(&text, &lists).unwrap();
+            let result: Vec<(OsString, OsString)> = profile
+                .into_iter()
+                .map(|var| (var.name, var.value))
+                .collect();
+            assert_eq!(
+                result,
+                [
+                    (OsString::from("PATH"), OsString::from("/a:/b;/c")),
+                    (OsString::from("PLAIN"), OsString::from("x")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn subset_with_placeholders() {
+        let mut env = env_vars(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let mut filter = Filter::default();
+        filter.add_name(OsStr::new("A"));
+        filter.add_name(OsStr::new("MISSING"));
+        filter.add_name(OsStr::new("MISSING"));
+        let placeholders = select(&mut env, &filter);
+        assert_eq!(placeholders, [OsString::from("MISSING")]);
+
+        let to_edit = with_placeholders(&env, &placeholders).unwrap();
+        let file = write_temp_file(&to_edit, &ListVars::none()).unwrap();
+        let text = fs::read_to_string(file.path()).unwrap();
+        let written: Vec<&str> = text.lines().filter(|l| !format::is_comment(l)).collect();
+        assert_eq!(written, ["A=1", "MISSING="]);
+
+        // saving the file as written leaves the placeholder empty
+        let mut edited = EnvVars::parse(
+            &mut text.as_bytes(),
+            &ListVars::none(),
+            Duplicates::Reject,
+            NameCheck::Kernel,
+        )
+        .unwrap();
+        drop_placeholders(&mut edited, &placeholders);
+        let result = diff(env.clone(), edited, &ListVars::none(), Renames::Detect);
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0].state, DiffState::Unchanged));
+
+        let mut edited = env_vars(&[("A", "1"), ("MISSING", "x")]);
+        drop_placeholders(&mut edited, &placeholders);
+        let result = diff(env, edited, &ListVars::none(), Renames::Detect);
+        let changes: Vec<_> = result
+            .iter()
+            .filter(|entry| !matches!(entry.state, DiffState::Unchanged))
+            .collect();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "MISSING");
+        assert!(matches!(changes[0].state, DiffState::Added));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let values = vec![
+            (
+                OsString::from("LATIN1"),
+                OsString::from_vec(b"caf\xe9".to_vec()),
+            ),
+            (
+                OsString::from("MULTI"),
+                OsString::from("line one\nline two\n"),
+            ),
+            (OsString::from("PATH"), OsString::from("/usr/bin:/bin")),
+            (OsString::from("PLAIN"), OsString::from("x")),
+        ];
+        let snapshot = EnvVars::try_from(
+            &mut values.clone().into_iter() as &mut dyn Iterator<Item = (OsString, OsString)>
+        )
+        .unwrap();
+        let saved = read_snapshot(&write_snapshot(&snapshot).unwrap()).unwrap();
+        let result: Vec<(OsString, OsString)> = saved
+            .clone()
+            .into_iter()
+            .map(|var| (var.name, var.value))
+            .collect();
+        assert_eq!(result, values);
+
+        let mut values = values;
+        values[2].1 = OsString::from("/opt/bin:/usr/bin:/bin");
+        values.remove(3);
+        values.push((OsString::from("ADDED"), OsString::from("1")));
+        let current = EnvVars::try_from(
+            &mut values.into_iter() as &mut dyn Iterator<Item = (OsString, OsString)>
+        )
+        .unwrap();
+
+        let lists = ListVars::new(&Config::default());
+        let result = diff(saved, current, &lists, Renames::Ignore);
+        let changes: Vec<(&OsString, &DiffState)> = result
+            .iter()
+            .filter(|entry| !matches!(entry.state, DiffState::Unchanged))
+            .map(|entry| (&entry.name, &entry.state))
+            .collect();
+        assert!(matches!(
+            changes[..],
+            [
+                (added, DiffState::Added),
+                (path, DiffState::Modified),
+                (plain, DiffState::Deleted)
+            ] if added == "ADDED" && path == "PATH" && plain == "PLAIN"
+        ));
+        let path = result.iter().find(|entry| entry.name == "PATH").unwrap();
+        assert_eq!(path.elements.as_ref().unwrap().len(), 3);
+    }
 }
@@ -0,0 +1,573 @@
+use crate::format;
+use crate::list::ElementState;
+use crate::{DiffEntry, DiffState};
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const HIGHLIGHT: &str = "\x1b[7m";
+const HIGHLIGHT_OFF: &str = "\x1b[27m";
+const RESET: &str = "\x1b[0m";
+
+pub const COLOR_CHOICES: [&str; 3] = ["auto", "always", "never"];
+pub const FORMATS: [&str; 2] = ["text", "json"];
+
+// `auto` colors only when stdout is a terminal and NO_COLOR isn't set
+pub fn use_color(choice: &str) -> bool {
+    match choice {
+        "always" => true,
+        "never" => false,
+        _ => {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            !no_color && io::stdout().is_terminal()
+        }
+    }
+}
+
+// splits a value into words and the single characters between them, so a
+// change is highlighted a whole word at a time
+fn tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if !c.is_alphanumeric() {
+            if start < i {
+                tokens.push(&s[start..i]);
+            }
+            tokens.push(&s[i..i + c.len_utf8()]);
+            start = i + c.len_utf8();
+        }
+    }
+    if start < s.len() {
+        tokens.push(&s[start..]);
+    }
+    tokens
+}
+
+// the byte ranges of `old` and `new` that differ, after dropping the words
+// they have in common at the start and the end
+fn changed_ranges(old: &str, new: &str) -> ((usize, usize), (usize, usize)) {
+    let old_tokens = tokens(old);
+    let new_tokens = tokens(new);
+
+    let prefix = old_tokens
+        .iter()
+        .zip(&new_tokens)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_tokens[prefix..]
+        .iter()
+        .rev()
+        .zip(new_tokens[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let len = |tokens: &[&str]| tokens.iter().map(|t| t.len()).sum::<usize>();
+    let range = |tokens: &[&str], total: usize| {
+        (
+            len(&tokens[..prefix]),
+            total - len(&tokens[tokens.len() - suffix..]),
+        )
+    };
+    (range(&old_tokens, old.len()), range(&new_tokens, new.len()))
+}
+
+struct Printer {
+    color: bool,
+}
+
+impl Printer {
+    fn paint(&self, color: &str, text: &str) -> String {
+        match self.color {
+            true => format!("{}{}{}", color, text, RESET),
+            false => String::from(text),
+        }
+    }
+
+    fn line(&self, color: &str, marker: char, name: &str, value: &str) -> String {
+        self.paint(color, &format!("{} {}={}", marker, name, value))
+    }
+
+    // like line(), with the changed part of the value shown in reverse video
+    fn highlighted_line(
+        &self,
+        color: &str,
+        marker: char,
+        name: &str,
+        value: &str,
+        (start, end): (usize, usize),
+    ) -> String {
+        if !self.color || start == end {
+            return self.line(color, marker, name, value);
+        }
+        format!(
+            "{}{} {}={}{}{}{}{}{}",
+            color,
+            marker,
+            name,
+            &value[..start],
+            HIGHLIGHT,
+            &value[start..end],
+            HIGHLIGHT_OFF,
+            &value[end..],
+            RESET
+        )
+    }
+
+    // names and values are written the way the edit file has them, so a
+    // line break or an invalid byte can't spill across lines or get lost
+    fn entry(&self, entry: &DiffEntry) -> Vec<String> {
+        let name = format::encode(entry.name.as_bytes(), true);
+        let old = entry
+            .old_value
+            .as_deref()
+            .map(|v| format::encode(v.as_bytes(), false));
+        let new = entry
+            .new_value
+            .as_deref()
+            .map(|v| format::encode(v.as_bytes(), false));
+
+        match entry.state {
+            DiffState::Added => vec![self.line(GREEN, '+', &name, &new.unwrap())],
+            DiffState::Deleted => vec![self.line(RED, '-', &name, &old.unwrap())],
+            DiffState::Unchanged => vec![self.line("", ' ', &name, &new.unwrap())],
+            DiffState::Renamed => {
+                let old_name = format::encode(entry.old_name.as_deref().unwrap().as_bytes(), true);
+                let names = format!("{} -> {}", old_name, name);
+                vec![self.line(YELLOW, 'R', &names, &new.unwrap())]
+            }
+            DiffState::Modified if entry.elements.is_some() => {
+                let mut lines = vec![self.paint(YELLOW, &format!("~ {}=(", name))];
+                for element in entry.elements.as_deref().unwrap() {
+                    let value = format::encode_element(&element.value);
+                    let (color, marker) = match element.state {
+                        ElementState::Unchanged => ("", ' '),
+                        ElementState::Added => (GREEN, '+'),
+                        ElementState::Removed => (RED, '-'),
+                        ElementState::Moved => (CYAN, '>'),
+                    };
+                    lines.push(self.paint(color, &format!("{}     {}", marker, value)));
+                }
+                lines.push(self.paint(YELLOW, "  )"));
+                lines
+            }
+            DiffState::Modified => {
+                let (old, new) = (old.unwrap(), new.unwrap());
+                let (old_range, new_range) = changed_ranges(&old, &new);
+                vec![
+                    self.highlighted_line(RED, '-', &name, &old, old_range),
+                    self.highlighted_line(GREEN, '+', &name, &new, new_range),
+                ]
+            }
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// JSON strings can only hold Unicode, so a name or value that isn't UTF-8
+// has its invalid bytes replaced in the string and gets a `KEY_bytes` array
+// alongside it holding the exact bytes
+fn json_field(key: &str, value: Option<&OsStr>) -> String {
+    let value = match value {
+        Some(value) => value.as_bytes(),
+        None => return format!("\"{}\": null", key),
+    };
+    let mut json = format!(
+        "\"{}\": {}",
+        key,
+        json_string(&String::from_utf8_lossy(value))
+    );
+    if std::str::from_utf8(value).is_err() {
+        let bytes: Vec<String> = value.iter().map(|b| b.to_string()).collect();
+        json.push_str(&format!(", \"{}_bytes\": [{}]", key, bytes.join(", ")));
+    }
+    json
+}
+
+fn json_entry(entry: &DiffEntry) -> String {
+    let state = match entry.state {
+        DiffState::Unchanged => "unchanged",
+        DiffState::Modified => "modified",
+        DiffState::Added => "added",
+        DiffState::Deleted => "deleted",
+        DiffState::Renamed => "renamed",
+    };
+    let mut json = format!(
+        "{{{}, \"state\": \"{}\", {}, {}",
+        json_field("name", Some(&entry.name)),
+        state,
+        json_field("old_value", entry.old_value.as_deref()),
+        json_field("new_value", entry.new_value.as_deref())
+    );
+    if let Some(old_name) = &entry.old_name {
+        json.push_str(&format!(", {}", json_field("old_name", Some(old_name))));
+    }
+
+    if let Some(elements) = &entry.elements {
+        let elements: Vec<String> = elements
+            .iter()
+            .map(|element| {
+                let state = match element.state {
+                    ElementState::Unchanged => "unchanged",
+                    ElementState::Added => "added",
+                    ElementState::Removed => "removed",
+                    ElementState::Moved => "moved",
+                };
+                format!(
+                    "{{\"state\": \"{}\", {}}}",
+                    state,
+                    json_field("value", Some(OsStr::from_bytes(&element.value)))
+                )
+            })
+            .collect();
+        json.push_str(&format!(", \"elements\": [{}]", elements.join(", ")));
+    }
+
+    json.push('}');
+    json
+}
+
+// one entry per line, so the output is easy to read as well as to parse
+fn json_diff(diff: &[DiffEntry], include_unchanged: bool) -> String {
+    let entries: Vec<String> = diff
+        .iter()
+        .filter(|entry| include_unchanged || !matches!(entry.state, DiffState::Unchanged))
+        .map(|entry| format!("  {}", json_entry(entry)))
+        .collect();
+    match entries.is_empty() {
+        true => String::from("[]"),
+        false => format!("[\n{}\n]", entries.join(",\n")),
+    }
+}
+
+pub fn print_json(diff: &[DiffEntry], include_unchanged: bool) -> io::Result<()> {
+    writeln!(
+        io::stdout().lock(),
+        "{}",
+        json_diff(diff, include_unchanged)
+    )
+}
+
+// picks the entries to print: every change, plus up to `context` unchanged
+// entries either side of it. None marks a gap between groups of entries,
+// like the hunks of `diff -U`
+fn visible(diff: &[DiffEntry], context: usize) -> Vec<Option<usize>> {
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !matches!(entry.state, DiffState::Unchanged))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut shown = vec![false; diff.len()];
+    for i in changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context).min(diff.len() - 1);
+        for flag in &mut shown[start..=end] {
+            *flag = true;
+        }
+    }
+
+    let mut indices = Vec::new();
+    for (i, show) in shown.iter().enumerate() {
+        if !show {
+            continue;
+        }
+        let after_gap = match indices.last() {
+            Some(Some(last)) => *last + 1 != i,
+            _ => i != 0,
+        };
+        if after_gap {
+            indices.push(None);
+        }
+        indices.push(Some(i));
+    }
+    if let Some(Some(last)) = indices.last() {
+        if *last != diff.len() - 1 {
+            indices.push(None);
+        }
+    }
+    indices
+}
+
+fn summary(diff: &[DiffEntry]) -> String {
+    let count = |state: fn(&DiffState) -> bool| diff.iter().filter(|e| state(&e.state)).count();
+    let added = count(|s| matches!(s, DiffState::Added));
+    let modified = count(|s| matches!(s, DiffState::Modified));
+    let deleted = count(|s| matches!(s, DiffState::Deleted));
+    let renamed = count(|s| matches!(s, DiffState::Renamed));
+
+    match added + modified + deleted + renamed {
+        0 => String::from("no changes"),
+        _ => format!(
+            "{} added, {} modified, {} deleted, {} renamed",
+            added, modified, deleted, renamed
+        ),
+    }
+}
+
+// with `include_unchanged` every entry is printed, otherwise only the
+// changes and `context` unchanged entries around each one
+fn diff_lines(
+    diff: &[DiffEntry],
+    color: bool,
+    context: usize,
+    include_unchanged: bool,
+) -> Vec<String> {
+    let printer = Printer { color };
+    let indices = match include_unchanged {
+        true => (0..diff.len()).map(Some).collect(),
+        false => visible(diff, context),
+    };
+
+    let mut lines = Vec::new();
+    for index in indices {
+        match index {
+            Some(i) => lines.extend(printer.entry(&diff[i])),
+            None => lines.push(printer.paint(CYAN, "  ...")),
+        }
+    }
+    lines.push(summary(diff));
+    lines
+}
+
+pub fn print_diff(
+    diff: &[DiffEntry],
+    color: bool,
+    context: usize,
+    include_unchanged: bool,
+) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    for line in diff_lines(diff, color, context, include_unchanged) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::list::{ElementDiff, ElementState};
+    use crate::output::{changed_ranges, diff_lines, json_diff, tokens, Printer};
+    use crate::{DiffEntry, DiffState};
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    #[test]
+    fn word_ranges() {
+        assert_eq!(tokens("a-bc d"), ["a", "-", "bc", " ", "d"]);
+
+        let old = "--opt=1 --verbose --level=info";
+        let new = "--opt=1 --verbose --level=debug";
+        let (old_range, new_range) = changed_ranges(old, new);
+        assert_eq!(&old[old_range.0..old_range.1], "info");
+        assert_eq!(&new[new_range.0..new_range.1], "debug");
+
+        // an insertion only highlights what was inserted
+        let (old_range, new_range) = changed_ranges("a b", "a x b");
+        assert_eq!(old_range.0, old_range.1);
+        assert_eq!(&"a x b"[new_range.0..new_range.1], "x ");
+
+        let (old_range, _) = changed_ranges("same", "same");
+        assert_eq!(old_range.0, old_range.1);
+    }
+
+    #[test]
+    fn colored_lines() {
+        let entry = DiffEntry {
+            name: OsString::from("LEVEL"),
+            old_name: None,
+            state: DiffState::Modified,
+            old_value: Some(OsString::from("info")),
+            new_value: Some(OsString::from("x info")),
+            elements: None,
+        };
+
+        let plain = Printer { color: false }.entry(&entry);
+        assert_eq!(plain, ["- LEVEL=info", "+ LEVEL=x info"]);
+
+        let escaped = DiffEntry {
+            name: OsString::from("NOTE"),
+            old_name: None,
+            state: DiffState::Modified,
+            old_value: Some(OsString::from("a b")),
+            new_value: Some(OsString::from_vec(b"a\nb\xff".to_vec())),
+            elements: None,
+        };
+        assert_eq!(
+            Printer { color: false }.entry(&escaped),
+            ["- NOTE=a b", "+ NOTE=$'a\\nb\\xff'"]
+        );
+        let list = DiffEntry {
+            elements: Some(vec![ElementDiff {
+                state: ElementState::Added,
+                value: b" padded".to_vec(),
+            }]),
+            ..escaped
+        };
+        assert_eq!(
+            Printer { color: false }.entry(&list),
+            ["~ NOTE=(", "+     $' padded'", "  )"]
+        );
+
+        let colored = Printer { color: true }.entry(&entry);
+        assert_eq!(
+            colored,
+            [
+                "\x1b[31m- LEVEL=info\x1b[0m",
+                "\x1b[32m+ LEVEL=\x1b[7mx \x1b[27minfo\x1b[0m"
+            ]
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let diff = vec![
+            DiffEntry {
+                name: OsString::from("QUOTED"),
+                old_name: None,
+                state: DiffState::Added,
+                old_value: None,
+                new_value: Some(OsString::from("say \"hi\"\n\u{1}")),
+                elements: None,
+            },
+            DiffEntry {
+                name: OsString::from("PATH"),
+                old_name: None,
+                state: DiffState::Modified,
+                old_value: Some(OsString::from("/bin")),
+                new_value: Some(OsString::from("/opt:/bin")),
+                elements: Some(vec![
+                    ElementDiff {
+                        state: ElementState::Added,
+                        value: b"/opt".to_vec(),
+                    },
+                    ElementDiff {
+                        state: ElementState::Unchanged,
+                        value: b"/bin".to_vec(),
+                    },
+                ]),
+            },
+            DiffEntry {
+                name: OsString::from("SAME"),
+                old_name: None,
+                state: DiffState::Unchanged,
+                old_value: Some(OsString::from("x")),
+                new_value: Some(OsString::from("x")),
+                elements: None,
+            },
+        ];
+
+        assert_eq!(
+            json_diff(&diff, false),
+            concat!(
+                "[\n",
+                "  {\"name\": \"QUOTED\", \"state\": \"added\", \"old_value\": null, ",
+                "\"new_value\": \"say \\\"hi\\\"\\n\\u0001\"},\n",
+                "  {\"name\": \"PATH\", \"state\": \"modified\", \"old_value\": \"/bin\", ",
+                "\"new_value\": \"/opt:/bin\", \"elements\": [",
+                "{\"state\": \"added\", \"value\": \"/opt\"}, ",
+                "{\"state\": \"unchanged\", \"value\": \"/bin\"}]}\n",
+                "]"
+            )
+        );
+        assert!(json_diff(&diff, true).contains("\"state\": \"unchanged\", \"old_value\": \"x\""));
+
+        let latin1 = DiffEntry {
+            name: OsString::from("LATIN1"),
+            old_name: None,
+            state: DiffState::Modified,
+            old_value: Some(OsString::from("cafe")),
+            new_value: Some(OsString::from_vec(b"caf\xe9".to_vec())),
+            elements: Some(vec![ElementDiff {
+                state: ElementState::Added,
+                value: b"caf\xe9".to_vec(),
+            }]),
+        };
+        assert_eq!(
+            json_diff(&[latin1], false),
+            concat!(
+                "[\n",
+                "  {\"name\": \"LATIN1\", \"state\": \"modified\", \"old_value\": \"cafe\", ",
+                "\"new_value\": \"caf\u{fffd}\", \"new_value_bytes\": [99, 97, 102, 233], ",
+                "\"elements\": [{\"state\": \"added\", \"value\": \"caf\u{fffd}\", ",
+                "\"value_bytes\": [99, 97, 102, 233]}]}\n",
+                "]"
+            )
+        );
+        assert_eq!(json_diff(&diff[2..], false), "[]");
+    }
+
+    fn entry(name: &str, state: DiffState) -> DiffEntry {
+        DiffEntry {
+            name: OsString::from(name),
+            old_name: None,
+            state,
+            old_value: Some(OsString::from("old")),
+            new_value: Some(OsString::from("new")),
+            elements: None,
+        }
+    }
+
+    #[test]
+    fn context_lines() {
+        let mut diff: Vec<DiffEntry> = ["A", "B", "C", "D", "E", "F", "G"]
+            .iter()
+            .map(|name| entry(name, DiffState::Unchanged))
+            .collect();
+        diff[1].state = DiffState::Modified;
+        diff[5].old_value = None;
+        diff[5].state = DiffState::Added;
+
+        assert_eq!(
+            diff_lines(&diff, false, 0, false),
+            [
+                "  ...",
+                "- B=old",
+                "+ B=new",
+                "  ...",
+                "+ F=new",
+                "  ...",
+                "1 added, 1 modified, 0 deleted, 0 renamed"
+            ]
+        );
+        assert_eq!(
+            diff_lines(&diff, false, 1, false),
+            [
+                "  A=new",
+                "- B=old",
+                "+ B=new",
+                "  C=new",
+                "  ...",
+                "  E=new",
+                "+ F=new",
+                "  G=new",
+                "1 added, 1 modified, 0 deleted, 0 renamed"
+            ]
+        );
+        assert_eq!(diff_lines(&diff, false, 2, false).len(), 9);
+        assert_eq!(diff_lines(&diff, false, 0, true).len(), 9);
+
+        assert_eq!(diff_lines(&diff[2..5], false, 3, false), ["no changes"]);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::EnvEditError;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// profiles are kept in the edit file format, one file per name, under
+// $XDG_DATA_HOME/envedit/profiles or ~/.local/share/envedit/profiles
+fn dir() -> Result<PathBuf, EnvEditError> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("envedit/profiles")),
+        _ => match env::var_os("HOME") {
+            Some(home) => Ok(PathBuf::from(home).join(".local/share/envedit/profiles")),
+            None => Err(EnvEditError::io(
+                "Failed to find the profile directory",
+                io::Error::new(io::ErrorKind::NotFound, "$HOME is not set"),
+            )),
+        },
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), EnvEditError> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(EnvEditError::Usage(format!(
+            "Invalid profile name '{}': names cannot be empty, start with '.' or contain '/'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn path(name: &str) -> Result<PathBuf, EnvEditError> {
+    validate_name(name)?;
+    Ok(dir()?.join(name))
+}
+
+fn not_found(name: &str) -> EnvEditError {
+    EnvEditError::Usage(format!("No profile named '{}'", name))
+}
+
+// returns None if there is no profile by that name
+pub fn read(name: &str) -> Result<Option<Vec<u8>>, EnvEditError> {
+    match fs::read(path(name)?) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(EnvEditError::io(
+            &format!("Failed to read profile '{}'", name),
+            e,
+        )),
+    }
+}
+
+pub fn read_existing(name: &str) -> Result<Vec<u8>, EnvEditError> {
+    read(name)?.ok_or_else(|| not_found(name))
+}
+
+// a --profile value names a saved profile, unless it contains a '/' or no
+// profile by that name exists, in which case it's read as a file path
+pub fn read_name_or_path(value: &OsStr) -> Result<Vec<u8>, EnvEditError> {
+    if let Some(name) = value.to_str() {
+        if validate_name(name).is_ok() {
+            if let Some(text) = read(name)? {
+                return Ok(text);
+            }
+        }
+    }
+    fs::read(value).map_err(|e| {
+        let context = format!("Failed to open profile {}", value.to_string_lossy());
+        EnvEditError::io(&context, e)
+    })
+}
+
+pub fn write(name: &str, text: &[u8]) -> Result<(), EnvEditError> {
+    let path = path(name)?;
+    let error = |e| EnvEditError::io(&format!("Failed to write profile '{}'", name), e);
+    fs::create_dir_all(dir()?).map_err(error)?;
+    fs::write(path, text).map_err(error)
+}
+
+pub fn remove(name: &str) -> Result<(), EnvEditError> {
+    match fs::remove_file(path(name)?) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(not_found(name)),
+        Err(e) => Err(EnvEditError::io(
+            &format!("Failed to remove profile '{}'", name),
+            e,
+        )),
+    }
+}
+
+pub fn list() -> Result<Vec<String>, EnvEditError> {
+    let entries = match fs::read_dir(dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(EnvEditError::io("Failed to list profiles", e)),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| EnvEditError::io("Failed to list profiles", e))?;
+        if let Ok(name) = entry.file_name().into_string() {
+            if validate_name(&name).is_ok() {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::validate_name;
+
+    #[test]
+    fn profile_names() {
+        assert!(validate_name("staging").is_ok());
+        assert!(validate_name("prod.eu-west").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name(".hidden").is_err());
+        assert!(validate_name("../escape").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{DiffEntry, DiffState, EXIT_NO_CHANGES};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Sh,
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub const SHELL_NAMES: [&str; 4] = ["sh", "bash", "zsh", "fish"];
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "sh" => Some(Shell::Sh),
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Shell::Sh => "sh",
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+
+    fn quote(self, value: &OsStr) -> Vec<u8> {
+        match self {
+            Shell::Sh | Shell::Bash | Shell::Zsh => quote_posix(value.as_bytes()),
+            Shell::Fish => quote_fish(value.as_bytes()),
+        }
+    }
+
+    // `name` has already been checked by is_exportable
+    fn export(self, name: &str, value: &OsStr) -> Vec<u8> {
+        let mut line = match self {
+            Shell::Sh | Shell::Bash | Shell::Zsh => format!("export {}=", name).into_bytes(),
+            Shell::Fish => format!("set -gx {} ", name).into_bytes(),
+        };
+        line.extend(self.quote(value));
+        line.push(b'\n');
+        line
+    }
+
+    fn unset(self, name: &str) -> Vec<u8> {
+        match self {
+            Shell::Sh | Shell::Bash | Shell::Zsh => format!("unset {}\n", name).into_bytes(),
+            Shell::Fish => format!("set -e {}\n", name).into_bytes(),
+        }
+    }
+}
+
+pub const EXPORTABLE_RULE: &str =
+    "shell variable names can only hold letters, digits and '_', and cannot start with a digit";
+
+// names that a shell will accept in `export NAME=...`; anything else is
+// legal in the environment but cannot be set from a script
+pub fn is_exportable(name: &OsStr) -> bool {
+    let mut bytes = name.as_bytes().iter();
+    match bytes.next() {
+        Some(b) if *b == b'_' || b.is_ascii_alphabetic() => {
+            bytes.all(|b| *b == b'_' || b.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+// the names of changes that apply_script has to leave out
+pub fn unexportable(diff: &[DiffEntry]) -> Vec<&OsStr> {
+    let mut names = Vec::new();
+    for entry in diff {
+        if matches!(entry.state, DiffState::Unchanged) {
+            continue;
+        }
+        for name in [Some(&entry.name), entry.old_name.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if !is_exportable(name) {
+                names.push(name.as_os_str());
+            }
+        }
+    }
+    names
+}
+
+// single quotes keep every byte literal except the single quote itself,
+// which has to be closed, escaped and reopened
+fn quote_posix(value: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(value.len() + 2);
+    quoted.push(b'\'');
+    for byte in value {
+        if *byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(*byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+// fish allows backslash escapes for '\' and '\'' inside single quotes. it
+// reads scripts as UTF-8, so bytes that aren't are written as unquoted \xHH
+// escapes between quoted runs
+fn quote_fish(value: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(value.len() + 2);
+    quoted.push(b'\'');
+    for chunk in value.utf8_chunks() {
+        for byte in chunk.valid().bytes() {
+            if byte == b'\'' || byte == b'\\' {
+                quoted.push(b'\\');
+            }
+            quoted.push(byte);
+        }
+        for byte in chunk.invalid() {
+            quoted.extend(format!("'\\x{:02x}'", byte).into_bytes());
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+pub fn apply_script(diff: &[DiffEntry], shell: Shell) -> Vec<u8> {
+    let mut script = Vec::new();
+    for entry in diff {
+        // exportable names are plain ASCII
+        let name = Some(entry.name.as_os_str())
+            .filter(|name| is_exportable(name))
+            .map(|name| name.to_str().unwrap());
+        match (&entry.state, name) {
+            (DiffState::Added | DiffState::Modified, Some(name)) => {
+                let value = entry.new_value.as_deref().unwrap();
+                script.extend(shell.export(name, value));
+            }
+            (DiffState::Deleted, Some(name)) => {
+                script.extend(shell.unset(name));
+            }
+            (DiffState::Renamed, name) => {
+                // either half still applies if the other name can't be used
+                // from a script
+                let old_name = entry.old_name.as_deref().unwrap();
+                if is_exportable(old_name) {
+                    script.extend(shell.unset(old_name.to_str().unwrap()));
+                }
+                if let Some(name) = name {
+                    let value = entry.new_value.as_deref().unwrap();
+                    script.extend(shell.export(name, value));
+                }
+            }
+            _ => {}
+        }
+    }
+    script
+}
+
+const POSIX_WRAPPER: &str = r#"envedit() {
+    local __envedit_script __envedit_status
+    __envedit_script="$(mktemp)" || return
+    command envedit --shell @SHELL@ --emit-to "$__envedit_script" "$@"
+    __envedit_status=$?
+    if [ "$__envedit_status" -eq 0 ]; then
+        . "$__envedit_script"
+    elif [ "$__envedit_status" -eq @NO_CHANGES@ ] &&
+        [ "$(cat -- "$__envedit_script")" = "@NO_CHANGES_NOTE@" ]; then
+        __envedit_status=0
+    fi
+    command rm -f -- "$__envedit_script"
+    return "$__envedit_status"
+}
+"#;
+
+const FISH_WRAPPER: &str = r#"function envedit --description 'Edit the environment of the current shell'
+    set -l __envedit_script (mktemp); or return
+    command envedit --shell fish --emit-to $__envedit_script $argv
+    set -l __envedit_status $status
+    if test $__envedit_status -eq 0
+        source $__envedit_script
+    else if test $__envedit_status -eq @NO_CHANGES@
+        set -l __envedit_note (cat -- $__envedit_script)
+        if test "$__envedit_note" = '@NO_CHANGES_NOTE@'
+            set __envedit_status 0
+        end
+    end
+    command rm -f -- $__envedit_script
+    return $__envedit_status
+end
+"#;
+
+// written in place of the apply script when the edit file was left
+// untouched, so the wrapper can tell that exit status apart from the same
+// status passed on from a command
+pub const NO_CHANGES_NOTE: &str = "# envedit: no changes\n";
+
+// the wrapper runs the real binary with the apply script redirected to a
+// temp file, so stdout and the editor keep the terminal, and then sources
+// the script into the shell that called it. an untouched edit file has
+// nothing to source and isn't a failure
+pub fn wrapper(shell: Shell) -> String {
+    let wrapper = match shell {
+        Shell::Sh | Shell::Bash | Shell::Zsh => POSIX_WRAPPER.replace("@SHELL@", shell.name()),
+        Shell::Fish => String::from(FISH_WRAPPER),
+    };
+    wrapper
+        .replace("@NO_CHANGES@", &EXIT_NO_CHANGES.to_string())
+        .replace("@NO_CHANGES_NOTE@", NO_CHANGES_NOTE.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::{apply_script, quote_fish, quote_posix, unexportable, Shell};
+    use crate::{DiffEntry, DiffState};
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    fn entry(name: &str, state: DiffState, old: Option<&str>, new: Option<&str>) -> DiffEntry {
+        DiffEntry {
+            name: OsString::from(name),
+            old_name: None,
+            state,
+            old_value: old.map(OsString::from),
+            new_value: new.map(OsString::from),
+            elements: None,
+        }
+    }
+
+    fn sample_diff() -> Vec<DiffEntry> {
+        vec![
+            entry("ADDED", DiffState::Added, None, Some("new")),
+            entry("DELETED", DiffState::Deleted, Some("old"), None),
+            entry("MODIFIED", DiffState::Modified, Some("a"), Some("b c")),
+            entry("SAME", DiffState::Unchanged, Some("x"), Some("x")),
+            entry("NOT VALID", DiffState::Added, None, Some("x")),
+            DiffEntry {
+                name: OsString::from("RENAMED"),
+                old_name: Some(OsString::from("ORIGINAL")),
+                state: DiffState::Renamed,
+                old_value: Some(OsString::from("v")),
+                new_value: Some(OsString::from("v")),
+                elements: None,
+            },
+            DiffEntry {
+                name: OsString::from("NOT VALID EITHER"),
+                old_name: Some(OsString::from("GONE")),
+                state: DiffState::Renamed,
+                old_value: Some(OsString::from("v")),
+                new_value: Some(OsString::from("v")),
+                elements: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn quote_values() {
+        assert_eq!(quote_posix(b""), b"''");
+        assert_eq!(quote_posix(b"plain"), b"'plain'");
+        assert_eq!(quote_posix(b"$HOME `id` \"x\""), b"'$HOME `id` \"x\"'");
+        assert_eq!(quote_posix(b"it's"), b"'it'\\''s'");
+        assert_eq!(quote_posix(b"a\nb"), b"'a\nb'");
+        assert_eq!(quote_posix(b"caf\xe9"), b"'caf\xe9'");
+
+        assert_eq!(quote_fish(b"it's"), b"'it\\'s'");
+        assert_eq!(quote_fish(b"a\\b"), b"'a\\\\b'");
+        assert_eq!(quote_fish(b"$HOME"), b"'$HOME'");
+        assert_eq!(quote_fish(b"caf\xe9!"), b"'caf'\\xe9'!'");
+    }
+
+    #[test]
+    fn script_from_diff() {
+        assert_eq!(
+            apply_script(&sample_diff(), Shell::Bash),
+            b"export ADDED='new'\nunset DELETED\nexport MODIFIED='b c'\n\
+              unset ORIGINAL\nexport RENAMED='v'\nunset GONE\n"
+        );
+        assert_eq!(
+            apply_script(&sample_diff(), Shell::Fish),
+            b"set -gx ADDED 'new'\nset -e DELETED\nset -gx MODIFIED 'b c'\n\
+              set -e ORIGINAL\nset -gx RENAMED 'v'\nset -e GONE\n"
+        );
+        assert_eq!(
+            unexportable(&sample_diff()),
+            ["NOT VALID", "NOT VALID EITHER"]
+        );
+
+        let latin1 = DiffEntry {
+            name: OsString::from("LATIN1"),
+            old_name: None,
+            state: DiffState::Added,
+            old_value: None,
+            new_value: Some(OsString::from_vec(b"caf\xe9".to_vec())),
+            elements: None,
+        };
+        assert_eq!(
+            apply_script(&[latin1], Shell::Sh),
+            b"export LATIN1='caf\xe9'\n"
+        );
+    }
+}
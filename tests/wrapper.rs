@@ -0,0 +1,55 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// runs `script` in bash with the `envedit init bash` wrapper defined and the
+// binary under test first in $PATH
+fn run_wrapped(script: &str) -> String {
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_envedit")).parent().unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap());
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(format!("eval \"$(envedit init bash)\"\n{}", script))
+        .env("PATH", path)
+        .env_remove("VISUAL")
+        .env_remove("EDITOR")
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn wrapper_with_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let editor = dir.path().join("editor");
+    fs::write(
+        &editor,
+        "#!/bin/sh\nsed -i 's/^FOO=.*/FOO=changed/' \"$1\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&editor, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = run_wrapped(&format!(
+        r#"export FOO=original
+envedit --editor '{editor}' FOO -- sh -c 'echo "child=$FOO"'
+echo "rc=$? parent=$FOO"
+envedit --editor '{editor}' FOO -- sh -c 'exit 9'
+echo "rc=$? parent=$FOO"
+envedit --editor true FOO
+echo "rc=$? parent=$FOO"
+envedit --editor '{editor}' FOO >/dev/null
+echo "rc=$? parent=$FOO"
+"#,
+        editor = editor.display()
+    ));
+    assert_eq!(
+        output,
+        "child=changed\n\
+         rc=0 parent=original\n\
+         rc=9 parent=original\n\
+         rc=0 parent=original\n\
+         rc=0 parent=changed\n"
+    );
+}